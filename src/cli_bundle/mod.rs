@@ -0,0 +1,55 @@
+use crate::cli_install::download_binaries::fetch_all;
+use crate::cli_install::download_urls::get_all_downloads;
+use crate::shared::bundle::{write_bundle, BundleManifest, BundledTool};
+use crate::shared::config::get_config;
+use crate::shared::lock::Lockfile;
+use crate::shared::sha256_of_file;
+use crate::shared::tools::Platform;
+use anyhow::{anyhow, Result};
+use std::cell::RefCell;
+use std::fs;
+use std::path::Path;
+
+pub async fn cli_bundle_create(platform: Option<Platform>, output: &Path) -> Result<()> {
+    let platform = match platform {
+        Some(platform) => platform,
+        None => Platform::current()?,
+    };
+
+    let downloads = get_all_downloads(platform, Lockfile::read()?.as_ref()).await?;
+    if downloads.is_empty() {
+        return Err(anyhow!("no tool versions configured, nothing to bundle"));
+    }
+
+    let staging = tempfile::tempdir()?;
+    let bundled = RefCell::new(vec![]);
+
+    fetch_all(&get_config()?, &downloads, |download, artifact| {
+        let file = format!("{}/{}", download.tool.name, download.artifact_name());
+        let target = staging.path().join(&file);
+        fs::create_dir_all(staging.path().join(&download.tool.name))?;
+        fs::copy(artifact, &target)?;
+
+        bundled.borrow_mut().push(BundledTool {
+            name: download.tool.name.clone(),
+            version: download.version.clone(),
+            file,
+            sha256: sha256_of_file(&target)?,
+            source: download.source.to_string(),
+            definition: download.tool.clone(),
+        });
+
+        Ok(())
+    })
+    .await?;
+
+    let mut tools = bundled.into_inner();
+    tools.sort_by(|a, b| a.name.cmp(&b.name));
+    let manifest = BundleManifest { platform, tools };
+
+    print!("{:<34} ", format!("writing {}", output.display()));
+    write_bundle(staging.path(), &manifest, output)?;
+    println!("success");
+
+    Ok(())
+}
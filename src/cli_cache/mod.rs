@@ -0,0 +1,59 @@
+use crate::shared::cache::Cache;
+use crate::shared::config::get_config;
+use crate::shared::since_epoch;
+use anyhow::Result;
+
+pub fn cli_cache_list() -> Result<()> {
+    let cache = Cache::open(&get_config()?.cache)?;
+    let entries = cache.list()?;
+
+    if entries.is_empty() {
+        println!("cache at {} is empty", cache.folder().display());
+        return Ok(());
+    }
+
+    println!("{:<14}{:>10}  {:<14}url", "sha256", "size", "last used");
+    for (url, entry) in &entries {
+        println!(
+            "{:<14}{:>10}  {:<14}{}",
+            entry.sha256.get(..12).unwrap_or(&entry.sha256),
+            format_size(entry.size),
+            format_age(entry.last_used),
+            url
+        );
+    }
+
+    let mut blobs: Vec<(&String, u64)> = entries.iter().map(|(_, e)| (&e.sha256, e.size)).collect();
+    blobs.sort();
+    blobs.dedup();
+    println!(
+        "\n{} of {} used in {}",
+        format_size(blobs.iter().map(|(_, size)| size).sum()),
+        format_size(cache.max_size()),
+        cache.folder().display()
+    );
+
+    Ok(())
+}
+
+pub fn cli_cache_prune(all: bool) -> Result<()> {
+    let cache = Cache::open(&get_config()?.cache)?;
+    let max_size = if all { 0 } else { cache.max_size() };
+
+    let freed = cache.prune(max_size)?;
+    println!("freed {}", format_size(freed));
+
+    Ok(())
+}
+
+fn format_size(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+fn format_age(timestamp: u64) -> String {
+    match since_epoch().as_secs().saturating_sub(timestamp) {
+        age if age < 3600 => format!("{} min ago", age / 60),
+        age if age < 86400 => format!("{} h ago", age / 3600),
+        age => format!("{} days ago", age / 86400),
+    }
+}
@@ -0,0 +1,203 @@
+use crate::shared::file_folder_paths::get_binary_path;
+use crate::shared::steps::{apply_steps, get_minikube_state, get_steps, CheckStatus, StepOptions};
+use crate::shared::{handle_output, AdminMode};
+use anyhow::{anyhow, Result};
+use kube::config::Kubeconfig;
+use std::io;
+use std::io::Write;
+use std::process::Command;
+
+/// What repairs a failed check: switching the kube context or applying an install step again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Fix {
+    KubeContext,
+    Step(&'static str),
+}
+
+/// Collects and prints the outcome of each check.
+#[derive(Default)]
+struct Diagnosis {
+    /// The fix of each problem found, if krunch can repair it.
+    problems: Vec<Option<Fix>>,
+}
+
+impl Diagnosis {
+    fn pass(&mut self, label: &str) {
+        println!("{:<34} ok", label);
+    }
+
+    fn fail(&mut self, label: &str, description: &str, remedy: &str, fix: Option<Fix>) {
+        println!("{:<34} {}", label, description);
+        println!("{:<34} -> {}", "", remedy);
+        self.problems.push(fix);
+    }
+
+    fn skip(&mut self, label: &str, reason: &str) {
+        println!("{:<34} skipped ({})", label, reason);
+    }
+
+    /// Reports a check that could not run at all as a problem of its own.
+    fn error(&mut self, label: &str, err: anyhow::Error) {
+        self.fail(
+            label,
+            &format!("check failed: {}", err),
+            "fix the error above and run `krunch doctor` again",
+            None,
+        );
+    }
+
+    fn fixes(&self) -> Vec<Fix> {
+        let mut fixes: Vec<Fix> = vec![];
+        for fix in self.problems.iter().flatten() {
+            if !fixes.contains(fix) {
+                fixes.push(*fix);
+            }
+        }
+        fixes
+    }
+}
+
+/// Runs every check, explains how to repair the failed ones and, with `fix`, re-applies the
+/// install steps that repair them.
+pub async fn cli_doctor(fix: bool, admin: AdminMode) -> Result<()> {
+    let mut diagnosis = diagnose().await;
+
+    if fix && !diagnosis.fixes().is_empty() {
+        println!();
+        run_fixes(&diagnosis.fixes(), admin).await?;
+
+        println!();
+        diagnosis = diagnose().await;
+    }
+
+    let count = diagnosis.problems.len();
+    if count == 0 {
+        println!("\nno problems found");
+        return Ok(());
+    }
+
+    let fixable = diagnosis.fixes().len();
+    let manual = diagnosis
+        .problems
+        .iter()
+        .filter(|fix| fix.is_none())
+        .count();
+    let hint = if !fix && fixable > 0 {
+        ", run `krunch doctor --fix` to repair them"
+    } else if manual > 0 {
+        ", follow the hints above to repair them"
+    } else {
+        ""
+    };
+
+    Err(anyhow!(
+        "{} problem{} found{}",
+        count,
+        if count == 1 { "" } else { "s" },
+        hint
+    ))
+}
+
+async fn diagnose() -> Diagnosis {
+    let mut diagnosis = Diagnosis::default();
+
+    let context_ok = match get_minikube_state() {
+        Ok(()) => {
+            diagnosis.pass("minikube");
+            check_kube_context(&mut diagnosis)
+        }
+        Err(reason) => {
+            diagnosis.fail(
+                "minikube",
+                &reason,
+                "install and start it, see https://minikube.sigs.k8s.io/docs/start/",
+                None,
+            );
+            diagnosis.skip("kube context", &reason);
+            false
+        }
+    };
+
+    for step in get_steps() {
+        for check in step.check().await {
+            match check.status {
+                CheckStatus::Ok(_) => diagnosis.pass(&check.label),
+                CheckStatus::Problem {
+                    description,
+                    remedy,
+                    fixable,
+                } => diagnosis.fail(
+                    &check.label,
+                    &description,
+                    &remedy,
+                    fixable.then_some(Fix::Step(step.name())),
+                ),
+                CheckStatus::Skipped(reason) => diagnosis.skip(&check.label, &reason),
+                // already reported as the kube context problem
+                CheckStatus::Error(_) if step.requires_minikube() && !context_ok => {
+                    diagnosis.skip(&check.label, "kube context is not minikube")
+                }
+                CheckStatus::Error(err) => diagnosis.error(&check.label, err),
+            }
+        }
+    }
+
+    diagnosis
+}
+
+/// Returns whether krunch can talk to minikube through the current kube context.
+fn check_kube_context(diagnosis: &mut Diagnosis) -> bool {
+    let label = "kube context";
+
+    match Kubeconfig::read() {
+        Ok(kubeconfig) if kubeconfig.current_context.as_deref() == Some("minikube") => {
+            diagnosis.pass(label);
+            true
+        }
+        Ok(kubeconfig) => {
+            diagnosis.fail(
+                label,
+                &format!(
+                    "current context is {}",
+                    kubeconfig.current_context.as_deref().unwrap_or("not set")
+                ),
+                "switch to minikube with `minikube update-context`",
+                Some(Fix::KubeContext),
+            );
+            false
+        }
+        Err(err) => {
+            diagnosis.error(label, anyhow!(err));
+            false
+        }
+    }
+}
+
+/// Switches the kube context first, as the steps that talk to the cluster need it.
+async fn run_fixes(fixes: &[Fix], admin: AdminMode) -> Result<()> {
+    if fixes.contains(&Fix::KubeContext) {
+        print!("{:<35}", "switching kube context to minikube");
+        io::stdout().flush().unwrap();
+        let output = Command::new(get_binary_path("minikube")?)
+            .arg("update-context")
+            .output()?;
+        match handle_output(output) {
+            Ok(output) => println!("{}", output),
+            Err(err) => println!("{}", err),
+        }
+    }
+
+    let steps: Vec<String> = fixes
+        .iter()
+        .filter_map(|fix| match fix {
+            Fix::Step(name) => Some(name.to_string()),
+            Fix::KubeContext => None,
+        })
+        .collect();
+    if steps.is_empty() {
+        return Ok(());
+    }
+
+    // a repair that fails leaves the others in place, doctor reports what is still broken
+    apply_steps(&steps, &StepOptions::default(), admin, false).await
+}
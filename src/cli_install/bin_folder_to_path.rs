@@ -1,58 +1,151 @@
+use crate::cli_install::docker_to_minikube::legacy_docker_exports;
+use crate::cli_remove::remove_environment_entries::remove_environment_entries;
 use crate::shared::file_folder_paths::{get_bin_folder, get_shell_profile_path};
+use crate::shared::managed_block::{get_block, set_profile_lines};
+use crate::shared::state::{record_change, Change};
+use crate::shared::steps::{Check, CheckStatus, Outcome, Step, StepOptions};
 use crate::shared::windows_registry::{read_from_environment, write_to_environment};
 use anyhow::Result;
-use std::fs;
+use async_trait::async_trait;
+use std::{env, fs};
 
-pub async fn add_bin_folder_to_path() -> Result<()> {
-    if cfg!(target_family = "unix") {
-        add_bin_folder_to_path_unix()?;
-    } else if cfg!(target_family = "windows") {
-        add_bin_folder_to_path_windows()?;
+/// Adds the bin folder with the tools' shims to the PATH.
+pub struct PathStep;
+
+#[async_trait(?Send)]
+impl Step for PathStep {
+    fn name(&self) -> &'static str {
+        "path"
+    }
+
+    fn describe(&self) -> &'static str {
+        "adding tools to PATH"
+    }
+
+    fn describe_revert(&self) -> Option<&'static str> {
+        Some("deleting PATH entry")
+    }
+
+    fn requires_minikube(&self) -> bool {
+        false
+    }
+
+    async fn check(&self) -> Vec<Check> {
+        let label = "tools in PATH";
+        let status = check_bin_folder().unwrap_or_else(CheckStatus::Error);
+        vec![Check::new(label, status)]
     }
 
-    Ok(())
+    async fn apply(&self, options: &StepOptions) -> Result<Outcome> {
+        add_bin_folder_to_path(options.dry_run)
+    }
+
+    async fn revert(&self, options: &StepOptions) -> Result<Outcome> {
+        remove_environment_entries(self.name(), options.dry_run)
+    }
 }
 
-fn add_bin_folder_to_path_unix() -> Result<()> {
-    let profile_path = get_shell_profile_path()?;
+fn check_bin_folder() -> Result<CheckStatus> {
     let bin_folder = get_bin_folder()?;
 
-    let mut data = fs::read_to_string(&profile_path)?;
-    data = data.trim().to_string();
+    if !is_bin_folder_in_path()? {
+        return Ok(CheckStatus::Problem {
+            description: format!("{} is not added to PATH", bin_folder.display()),
+            remedy: "let krunch add it to your shell profile".to_string(),
+            fixable: true,
+        });
+    }
 
-    if data.contains(&bin_folder.display().to_string()) {
-        println!("already done");
+    let path = env::var_os("PATH").unwrap_or_default();
+    if env::split_paths(&path).any(|folder| folder == bin_folder) {
+        return Ok(CheckStatus::Ok("ok".to_string()));
+    }
+
+    let profile = get_shell_profile_path()
+        .map(|profile| format!("run `source {}`", profile.display()))
+        .unwrap_or("log out and in again".to_string());
+    Ok(CheckStatus::Problem {
+        description: format!("{} is not in the PATH of this shell", bin_folder.display()),
+        remedy: format!("open a new shell or {}", profile),
+        fixable: false,
+    })
+}
+
+pub fn add_bin_folder_to_path(dry_run: bool) -> Result<Outcome> {
+    if cfg!(target_family = "windows") {
+        add_bin_folder_to_path_windows(dry_run)
     } else {
-        let conditional_path_export = format!(
-            "if [[ \":$PATH:\" != *\":{}:\"* ]]; then export PATH=\"{}:$PATH\"; fi",
-            bin_folder.display(),
-            bin_folder.display()
-        );
-
-        data.push_str("\n\n# krunch\n");
-        data.push_str(conditional_path_export.as_str());
-        data.push_str("\n\n");
-
-        fs::write(profile_path, data)?;
-        println!("success");
+        add_bin_folder_to_path_unix(dry_run)
+    }
+}
+
+/// Whether krunch's block in the shell profile or, on Windows, the user environment adds the bin
+/// folder to the PATH.
+pub fn is_bin_folder_in_path() -> Result<bool> {
+    let bin_folder = get_bin_folder()?;
+
+    if cfg!(target_family = "windows") {
+        let bin_folder = bin_folder.display().to_string().replace('/', "\\");
+        return Ok(read_from_environment("Path")?.contains(&bin_folder));
+    }
+
+    let data = fs::read_to_string(get_shell_profile_path()?)?;
+    let export = get_path_export()?;
+    Ok(get_block(&data).is_some_and(|lines| lines.contains(&export.as_str())))
+}
+
+pub fn get_path_export() -> Result<String> {
+    let bin_folder = get_bin_folder()?;
+    Ok(format!(
+        "if [[ \":$PATH:\" != *\":{}:\"* ]]; then export PATH=\"{}:$PATH\"; fi",
+        bin_folder.display(),
+        bin_folder.display()
+    ))
+}
+
+fn add_bin_folder_to_path_unix(dry_run: bool) -> Result<Outcome> {
+    if is_bin_folder_in_path()? {
+        return Ok(Outcome::AlreadyDone);
+    }
+
+    let export = get_path_export()?;
+    // krunch versions before the block wrote the same line below a `# krunch` comment, followed by
+    // the docker variables, which need the comment to be recognised as well
+    let legacy = |lines: &[&str]| match lines {
+        ["# krunch", line, rest @ ..] if *line == export && legacy_docker_exports(rest) > 0 => 1..2,
+        ["# krunch", line, ..] if *line == export => 0..2,
+        [line, ..] if *line == export => 0..1,
+        _ => 0..0,
     };
 
-    Ok(())
+    set_profile_lines(
+        "path",
+        &get_shell_profile_path()?,
+        vec![export.clone()],
+        legacy,
+        dry_run,
+    )
 }
 
-fn add_bin_folder_to_path_windows() -> Result<()> {
+fn add_bin_folder_to_path_windows(dry_run: bool) -> Result<Outcome> {
     let current_path = read_from_environment("Path")?;
     let bin_folder = get_bin_folder()?.display().to_string().replace('/', "\\");
 
-    if current_path.contains(&bin_folder) {
-        println!("already done");
+    if is_bin_folder_in_path()? {
+        return Ok(Outcome::AlreadyDone);
+    }
+
+    let divider = if current_path.ends_with(';') { "" } else { ";" };
+    let new_path = format!("{}{}{};", current_path, divider, bin_folder);
+
+    if dry_run {
+        Ok(Outcome::Message(format!(
+            "would set the user variable Path to {}",
+            new_path
+        )))
     } else {
-        let divider = if current_path.ends_with(';') { "" } else { ";" };
-        let new_path = format!("{}{}{};", current_path, divider, bin_folder);
         write_to_environment("Path", new_path)?;
-
-        println!("success");
+        record_change("path", Change::UserPathEntry { folder: bin_folder })?;
+        Ok(Outcome::Done)
     }
-
-    Ok(())
 }
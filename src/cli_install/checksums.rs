@@ -0,0 +1,212 @@
+use crate::cli_install::download_urls::Download;
+use crate::shared::config::KrunchConfig;
+use crate::shared::file_folder_paths::{get_config_file_path, get_recorded_checksums_path};
+use crate::shared::http::fetch_text;
+use crate::shared::mirrors::get_candidate_urls;
+use crate::shared::{is_sha256, sha256_of_file};
+use anyhow::{anyhow, Result};
+use reqwest::Client;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use tempfile::NamedTempFile;
+
+/// Returns the expected SHA-256 digest of a download, either pinned in the config, locked,
+/// recorded on first use or published next to the download. `None` if there is none yet and it
+/// may be installed without one or be recorded.
+pub async fn get_expected_digest(
+    download: &Download,
+    client: &Client,
+    config: &KrunchConfig,
+) -> Result<Option<String>> {
+    if let Some(sha256) = get_pinned_digest(download)? {
+        return Ok(Some(sha256));
+    }
+
+    if let Some(checksum_url) = &download.checksum {
+        let candidates = get_candidate_urls(
+            &config.mirrors,
+            config.upstream_fallback,
+            &download.tool.name,
+            checksum_url,
+        )?;
+        let mut contents = None;
+        let mut errors = vec![];
+        for candidate in &candidates {
+            match fetch_text(client, &config.download, candidate).await {
+                Ok(text) => {
+                    contents = Some(text);
+                    break;
+                }
+                Err(err) => errors.push(format!("{}: {}", candidate, err)),
+            }
+        }
+        let contents = contents.ok_or(anyhow!(
+            "failed to fetch checksum for {}: {}",
+            download.target(),
+            errors.join(", ")
+        ))?;
+
+        return match parse_checksum_file(&contents, &download.artifact_name()) {
+            Some(sha256) => Ok(Some(sha256)),
+            None => Err(anyhow!(
+                "no checksum for {} found in '{}'",
+                download.target(),
+                checksum_url
+            )),
+        };
+    }
+
+    if config.require_checksums && !config.record_checksums {
+        return Err(anyhow!(
+            "no checksum available for '{}', pin one under checksums in {}, record one with \
+             `krunch lock` or set `\"record_checksums\": true` to trust its first download",
+            download.source,
+            get_config_file_path()?.display()
+        ));
+    }
+
+    Ok(None)
+}
+
+/// Returns the digest pinned in the config or the lock file in lowercase, which needs no network.
+pub fn get_pinned_digest(download: &Download) -> Result<Option<String>> {
+    match &download.sha256 {
+        Some(sha256) if !is_sha256(sha256) => Err(anyhow!(
+            "invalid checksum '{}' for {}, a SHA-256 digest has 64 hex characters",
+            sha256,
+            download.target()
+        )),
+        Some(sha256) => Ok(Some(sha256.to_lowercase())),
+        None => Ok(None),
+    }
+}
+
+/// Returns the digest recorded for `url` on its first download.
+pub fn get_recorded_digest(url: &str) -> Result<Option<String>> {
+    Ok(read_recorded_digests(&get_recorded_checksums_path()?)?.remove(url))
+}
+
+/// Records the digest of a download that had no checksum, so that later downloads of `url` are
+/// checked against it.
+pub fn record_digest(url: &str, sha256: &str) -> Result<()> {
+    record_digest_in(&get_recorded_checksums_path()?, url, sha256)
+}
+
+fn read_recorded_digests(path: &Path) -> Result<BTreeMap<String, String>> {
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+
+    serde_json::from_str(&fs::read_to_string(path)?)
+        .map_err(|err| anyhow!("failed to parse {}: {}", path.display(), err))
+}
+
+fn record_digest_in(path: &Path, url: &str, sha256: &str) -> Result<()> {
+    let mut digests = read_recorded_digests(path)?;
+    digests.insert(url.to_string(), sha256.to_lowercase());
+
+    let folder = path
+        .parent()
+        .ok_or(anyhow!("invalid checksums path {}", path.display()))?;
+    fs::create_dir_all(folder)?;
+    let mut tmp_file = NamedTempFile::new_in(folder)?;
+    serde_json::to_writer_pretty(&mut tmp_file, &digests)?;
+    tmp_file.persist(path)?;
+
+    Ok(())
+}
+
+pub fn verify_digest(path: &Path, expected: &str, download: &Download) -> Result<()> {
+    let actual = sha256_of_file(path)?;
+
+    if actual != expected {
+        return Err(anyhow!(
+            "checksum mismatch for '{}': expected {}, got {}",
+            download.source,
+            expected,
+            actual
+        ));
+    }
+
+    Ok(())
+}
+
+/// Reads a digest either from a file containing only the digest, or from a checksums.txt with
+/// one `<digest>  <file name>` line per artifact.
+fn parse_checksum_file(contents: &str, artifact_name: &str) -> Option<String> {
+    let lines: Vec<Vec<&str>> = contents
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<&str>>())
+        .filter(|parts| !parts.is_empty())
+        .collect();
+
+    for parts in &lines {
+        match parts.as_slice() {
+            [digest] if lines.len() == 1 && is_sha256(digest) => {
+                return Some(digest.to_lowercase())
+            }
+            [digest, name] if is_sha256(digest) => {
+                let name = name.trim_start_matches('*');
+                if name == artifact_name || (lines.len() == 1 && name.ends_with(artifact_name)) {
+                    return Some(digest.to_lowercase());
+                }
+            }
+            _ => (),
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGEST: &str = "2c8d9f2e5bb42a2a4ac8a4bc9cf3bd1f9f2d44d8e0a3f54a2c1b1fbe6e3b7b1f";
+
+    #[test]
+    fn test_parse_checksum_file() {
+        assert_eq!(
+            parse_checksum_file(&format!("{}\n", DIGEST), "kubectl"),
+            Some(DIGEST.to_string())
+        );
+        assert_eq!(
+            parse_checksum_file(
+                &format!("{}  helm-v3.14.4-linux-amd64.tar.gz", DIGEST),
+                "helm-v3.14.4-linux-amd64.tar.gz"
+            ),
+            Some(DIGEST.to_string())
+        );
+
+        let checksums = format!(
+            "{}  *buildx-v0.10.4.darwin-arm64\n{} *buildx-v0.10.4.linux-amd64\n",
+            "0".repeat(64),
+            DIGEST
+        );
+        assert_eq!(
+            parse_checksum_file(&checksums, "buildx-v0.10.4.linux-amd64"),
+            Some(DIGEST.to_string())
+        );
+        assert_eq!(
+            parse_checksum_file(&checksums, "buildx-v0.10.4.linux-arm64"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_record_digest() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("krunch/checksums.json");
+        let url = "https://dl.filippo.io/mkcert/v1.4.4?for=linux/amd64";
+
+        assert!(read_recorded_digests(&path).unwrap().is_empty());
+
+        record_digest_in(&path, url, &DIGEST.to_uppercase()).unwrap();
+        record_digest_in(&path, "https://example.com/k9s.tar.gz", &"0".repeat(64)).unwrap();
+
+        let digests = read_recorded_digests(&path).unwrap();
+        assert_eq!(digests.len(), 2);
+        assert_eq!(digests[url], DIGEST);
+    }
+}
@@ -1,18 +1,293 @@
-use crate::shared::file_folder_paths::{get_binary_path, Binary};
+use crate::cli_remove::remove_ca_and_tls::remove_ca_and_tls_secret;
+use crate::shared::file_folder_paths::get_binary_path;
+use crate::shared::state::{record_change, Change, State};
+use crate::shared::steps::{Check, CheckStatus, Outcome, Step, StepOptions};
 use crate::shared::{get_minikube_client, handle_output, MINIKUBE_HOST, TLS_SECRET};
 use anyhow::{anyhow, Result};
+use async_trait::async_trait;
 use base64::engine::general_purpose;
 use base64::Engine;
 use k8s_openapi::api::core::v1::Secret;
-use kube::api::PostParams;
+use kube::api::{DeleteParams, PostParams, Preconditions};
 use kube::{Api, Error};
 use std::fs;
 use std::fs::File;
 use std::io::Read;
+use std::path::PathBuf;
 use std::process::Command;
+use std::time::Duration;
+use x509_parser::pem::parse_x509_pem;
+use x509_parser::time::ASN1Time;
+
+/// TLS secrets that expire within this time are renewed in advance.
+const TLS_RENEWAL_WINDOW: Duration = Duration::from_secs(7 * 24 * 3600);
+
+/// Creates a local CA with mkcert and a TLS secret for k8s.local signed by it.
+pub struct TlsStep;
+
+#[async_trait(?Send)]
+impl Step for TlsStep {
+    fn name(&self) -> &'static str {
+        "tls"
+    }
+
+    fn describe(&self) -> &'static str {
+        "creating CA and TLS secret"
+    }
+
+    fn describe_revert(&self) -> Option<&'static str> {
+        Some("deleting CA and TLS secret")
+    }
+
+    fn requires_admin(&self) -> bool {
+        true
+    }
+
+    fn dependencies(&self) -> &'static [&'static str] {
+        &["tools"]
+    }
+
+    async fn check(&self) -> Vec<Check> {
+        vec![check_local_ca(), check_tls_secret().await]
+    }
+
+    async fn apply(&self, options: &StepOptions) -> Result<Outcome> {
+        create_ca_and_tls(options.dry_run).await
+    }
+
+    async fn revert(&self, options: &StepOptions) -> Result<Outcome> {
+        remove_ca_and_tls_secret(self.name(), options.dry_run).await
+    }
+}
+
+fn check_local_ca() -> Check {
+    let label = "mkcert CA";
+    if !get_binary_path("mkcert").is_ok_and(|mkcert| mkcert.exists()) {
+        return Check::new(
+            label,
+            CheckStatus::Skipped("mkcert is not installed".to_string()),
+        );
+    }
+
+    match get_local_ca_state() {
+        Ok("ok") => Check::ok(label, "ok"),
+        Ok(state) => Check::problem(
+            label,
+            state,
+            "create and trust it with `mkcert -install`",
+            true,
+        ),
+        Err(err) => Check::new(label, CheckStatus::Error(err)),
+    }
+}
+
+async fn check_tls_secret() -> Check {
+    let label = "TLS secret";
+    let remedy = "replace it with a new certificate from the mkcert CA";
+
+    let (expiry, uid) = match get_tls_secret().await {
+        Ok(Some(secret)) => secret,
+        Ok(None) => return Check::problem(label, "missing", "let krunch create it", true),
+        Err(err) => return Check::unavailable(label, err),
+    };
+    // krunch only renews a secret it created, see `create_ca_and_tls`
+    let owned = match is_own_secret(&uid) {
+        Ok(owned) => owned,
+        Err(err) => return Check::unavailable(label, err),
+    };
+    let not_owned = format!(
+        "secret default/{} was not created by krunch, renew it yourself or delete it to let \
+        krunch create a new one",
+        TLS_SECRET
+    );
+
+    match get_expiry_state(&expiry) {
+        ExpiryState::Expired => Check::problem(
+            label,
+            &format!("the certificate expired on {}", expiry),
+            if owned { remedy } else { &not_owned },
+            owned,
+        ),
+        ExpiryState::Expiring => Check::problem(
+            label,
+            &format!("the certificate expires on {}", expiry),
+            if owned { remedy } else { &not_owned },
+            owned,
+        ),
+        ExpiryState::Valid if owned => Check::ok(label, &format!("ok (expires on {})", expiry)),
+        ExpiryState::Valid => Check::ok(
+            label,
+            &format!("ok (expires on {}, not created by krunch)", expiry),
+        ),
+    }
+}
+
+#[derive(PartialEq)]
+enum ExpiryState {
+    Valid,
+    /// Expires within [`TLS_RENEWAL_WINDOW`].
+    Expiring,
+    Expired,
+}
+
+fn get_expiry_state(expiry: &ASN1Time) -> ExpiryState {
+    let now = ASN1Time::now();
+    if *expiry <= now {
+        ExpiryState::Expired
+    } else if expiry.timestamp() - now.timestamp() < TLS_RENEWAL_WINDOW.as_secs() as i64 {
+        ExpiryState::Expiring
+    } else {
+        ExpiryState::Valid
+    }
+}
+
+/// Creates the CA and the TLS secret if they are missing, and renews the secret when its
+/// certificate expired or is about to. A secret that krunch didn't create is never replaced.
+pub async fn create_ca_and_tls(dry_run: bool) -> Result<Outcome> {
+    let secret = get_tls_secret().await?;
+    let expiring = secret
+        .as_ref()
+        .filter(|(expiry, _)| get_expiry_state(expiry) != ExpiryState::Valid);
+    let renew = match expiring {
+        Some((_, uid)) => is_own_secret(uid)?,
+        None => false,
+    };
+    let not_owned = expiring.filter(|_| !renew).map(|(expiry, _)| {
+        format!(
+            "secret default/{} expires on {} but was not created by krunch, left alone",
+            TLS_SECRET, expiry
+        )
+    });
+
+    if dry_run {
+        // mkcert itself may only be downloaded by the install being planned
+        let ca_state = if get_binary_path("mkcert")?.exists() {
+            get_local_ca_state()?
+        } else {
+            "missing"
+        };
+
+        let mut actions = vec![];
+        if ca_state != "ok" {
+            actions.push("run `mkcert -install` to add a local CA to the trust store".to_string());
+        }
+        if secret.is_none() {
+            actions.push(format!(
+                "create secret default/{} for {}",
+                TLS_SECRET, MINIKUBE_HOST
+            ));
+        } else if renew {
+            actions.push(format!(
+                "replace the expiring certificate in secret default/{}",
+                TLS_SECRET
+            ));
+        }
+
+        return Ok(match (actions.is_empty(), not_owned) {
+            (true, None) => Outcome::AlreadyDone,
+            (true, Some(not_owned)) => Outcome::Message(not_owned),
+            (false, None) => Outcome::Message(format!("would {}", actions.join(" and "))),
+            (false, Some(not_owned)) => {
+                Outcome::Message(format!("would {}\n{}", actions.join(" and "), not_owned))
+            }
+        });
+    }
 
-pub async fn create_ca_and_tls() -> Result<()> {
     install_local_ca()?;
+
+    match secret {
+        Some((_, uid)) if renew => {
+            renew_tls_secret(&uid).await?;
+            Ok(Outcome::Message("renewed expiring certificate".to_string()))
+        }
+        Some(_) => Ok(not_owned.map_or(Outcome::AlreadyDone, Outcome::Message)),
+        None => {
+            create_certificate_files()?;
+            install_tls_secret().await
+        }
+    }
+}
+
+/// Whether the secret with `uid` is the one krunch created and recorded.
+fn is_own_secret(uid: &str) -> Result<bool> {
+    Ok(State::read()?.changes("tls").iter().any(|change| {
+        matches!(change, Change::Secret { uid: recorded, .. } if recorded == uid && !uid.is_empty())
+    }))
+}
+
+/// Whether mkcert created its CA and the operating system trusts it, checked without admin
+/// rights by looking for the CA in the system's certificate store.
+pub fn get_local_ca_state() -> Result<&'static str> {
+    let output = Command::new(get_binary_path("mkcert")?)
+        .arg("-CAROOT")
+        .output()?;
+    let ca_root = PathBuf::from(handle_output(output)?);
+
+    let ca_pem = match fs::read_to_string(ca_root.join("rootCA.pem")) {
+        Ok(ca_pem) => ca_pem,
+        Err(_) => return Ok("missing"),
+    };
+    let ca_base64: String = ca_pem
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .collect();
+    let ca_der = general_purpose::STANDARD.decode(ca_base64)?;
+
+    let trusted = rustls_native_certs::load_native_certs()?
+        .iter()
+        .any(|cert| cert.0 == ca_der);
+
+    Ok(if trusted {
+        "ok"
+    } else {
+        "created, but not trusted"
+    })
+}
+
+/// When the certificate in the TLS secret expires and the secret's uid, `None` if there is no
+/// secret.
+pub async fn get_tls_secret() -> Result<Option<(ASN1Time, String)>> {
+    let client = get_minikube_client().await?;
+    let secrets: Api<Secret> = Api::namespaced(client, "default");
+
+    let secret = match secrets.get_opt(TLS_SECRET).await? {
+        Some(secret) => secret,
+        None => return Ok(None),
+    };
+    let uid = secret.metadata.uid.clone().unwrap_or_default();
+    let tls_crt = secret
+        .data
+        .and_then(|mut data| data.remove("tls.crt"))
+        .ok_or(anyhow!("secret {} has no tls.crt", TLS_SECRET))?;
+
+    let (_, pem) = parse_x509_pem(&tls_crt.0)
+        .map_err(|err| anyhow!("failed to read tls.crt of secret {}: {}", TLS_SECRET, err))?;
+    let certificate = pem
+        .parse_x509()
+        .map_err(|err| anyhow!("failed to read tls.crt of secret {}: {}", TLS_SECRET, err))?;
+
+    Ok(Some((certificate.validity().not_after, uid)))
+}
+
+/// Replaces the TLS secret with one holding a new certificate, e.g. after the old one expired.
+/// Deleting it only succeeds while it is still the secret with `uid`.
+async fn renew_tls_secret(uid: &str) -> Result<()> {
+    let client = get_minikube_client().await?;
+    let secrets: Api<Secret> = Api::namespaced(client, "default");
+
+    let params = DeleteParams {
+        preconditions: Some(Preconditions {
+            uid: Some(uid.to_string()),
+            resource_version: None,
+        }),
+        ..Default::default()
+    };
+    match secrets.delete(TLS_SECRET, &params).await {
+        Ok(_) => (),
+        Err(Error::Api(inner)) if inner.reason == "NotFound" => (),
+        Err(err) => return Err(anyhow!(err)),
+    }
+
     create_certificate_files()?;
     install_tls_secret().await?;
 
@@ -20,17 +295,22 @@ pub async fn create_ca_and_tls() -> Result<()> {
 }
 
 fn install_local_ca() -> Result<()> {
-    let output = Command::new(get_binary_path(Binary::Mkcert)?)
+    let existed = get_local_ca_state()? == "ok";
+
+    let output = Command::new(get_binary_path("mkcert")?)
         .arg("-install")
-        .output()
-        .expect("failed to execute process");
+        .output()?;
 
     handle_output(output)?;
 
+    if !existed {
+        record_change("tls", Change::LocalCa)?;
+    }
+
     Ok(())
 }
 
-async fn install_tls_secret() -> Result<()> {
+async fn install_tls_secret() -> Result<Outcome> {
     let mut file = File::open(format!("{}.pem", MINIKUBE_HOST))?;
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
@@ -61,36 +341,29 @@ async fn install_tls_secret() -> Result<()> {
         "type": "kubernetes.io/tls"
     }))?;
 
-    let result = secrets.create(&PostParams::default(), &secret).await;
-
-    handle_resource_creation_result(result)?;
-
-    Ok(())
+    match secrets.create(&PostParams::default(), &secret).await {
+        Ok(created) => {
+            record_change(
+                "tls",
+                Change::Secret {
+                    namespace: "default".to_string(),
+                    name: TLS_SECRET.to_string(),
+                    uid: created.metadata.uid.unwrap_or_default(),
+                },
+            )?;
+            Ok(Outcome::Done)
+        }
+        Err(Error::Api(inner)) if inner.reason == "AlreadyExists" => Ok(Outcome::AlreadyDone),
+        Err(err) => Err(anyhow!(err)),
+    }
 }
 
 fn create_certificate_files() -> Result<()> {
-    let output = Command::new(get_binary_path(Binary::Mkcert)?)
+    let output = Command::new(get_binary_path("mkcert")?)
         .arg(MINIKUBE_HOST)
-        .output()
-        .expect("failed to execute process");
+        .output()?;
 
     handle_output(output)?;
 
     Ok(())
 }
-
-fn handle_resource_creation_result<T>(result: kube::Result<T, Error>) -> Result<()> {
-    match result {
-        Ok(_) => println!("success"),
-        Err(Error::Api(inner)) => {
-            if inner.reason == "AlreadyExists" {
-                println!("already done");
-            }
-        }
-        Err(err) => {
-            return Err(anyhow!(err));
-        }
-    }
-
-    Ok(())
-}
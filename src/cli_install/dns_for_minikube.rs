@@ -1,67 +1,227 @@
-use crate::shared::file_folder_paths::{get_binary_path, get_etc_hosts_path, Binary};
-use crate::shared::{handle_output, update_etc_hosts, LINE_ENDING};
+use crate::cli_remove::remove_dns_for_minikube::remove_dns_for_minikube;
+use crate::shared::file_folder_paths::{get_binary_path, get_etc_hosts_path};
+use crate::shared::hosts_file::HostsFile;
+use crate::shared::state::{Change, State};
+use crate::shared::steps::{Check, Outcome, Step, StepOptions};
+use crate::shared::{get_diff, handle_output, update_etc_hosts, EntryState, MINIKUBE_HOST};
 use anyhow::Result;
+use async_trait::async_trait;
 use std::fs;
+use std::net::IpAddr;
 use std::process::Command;
 
-pub fn add_dns_for_minikube() -> Result<()> {
-    let etc_hosts_path = get_etc_hosts_path()?;
-    let mut data = fs::read_to_string(&etc_hosts_path)?;
-    data = data.trim().to_string();
+/// Maps k8s.local to the IP of minikube in the hosts file.
+pub struct DnsStep;
 
-    let minikube_ip = get_minikube_ip()?;
+#[async_trait(?Send)]
+impl Step for DnsStep {
+    fn name(&self) -> &'static str {
+        "dns"
+    }
 
-    if data.contains(&minikube_ip) {
-        println!("already done");
-    } else if data.contains("k8s.local") {
-        let data = update_dns_data(data, minikube_ip);
-        update_etc_hosts(data)?;
+    fn describe(&self) -> &'static str {
+        "creating DNS entry"
+    }
 
-        println!("minikube ip updated");
-    } else {
-        let data = add_dns_data(data, minikube_ip);
-        update_etc_hosts(data)?;
+    fn describe_revert(&self) -> Option<&'static str> {
+        Some("deleting DNS entry")
+    }
 
-        println!("success");
+    fn requires_admin(&self) -> bool {
+        true
     }
 
-    Ok(())
+    async fn check(&self) -> Vec<Check> {
+        let label = format!("DNS entry for {}", MINIKUBE_HOST);
+        let hosts = get_etc_hosts_path()
+            .map(|path| path.display().to_string())
+            .unwrap_or("the hosts file".to_string());
+        let remedy = format!(
+            "map {} to the output of `minikube ip` in {}",
+            MINIKUBE_HOST, hosts
+        );
+
+        match get_dns_state() {
+            Ok(EntryState::UpToDate) => vec![Check::ok(&label, "ok")],
+            Ok(EntryState::Outdated) => vec![Check::problem(
+                &label,
+                &format!("{} points to an old minikube IP", MINIKUBE_HOST),
+                &remedy,
+                true,
+            )],
+            Ok(EntryState::Missing) => vec![Check::problem(
+                &label,
+                &format!("{} is missing in {}", MINIKUBE_HOST, hosts),
+                &remedy,
+                true,
+            )],
+            Err(err) => vec![Check::unavailable(&label, err)],
+        }
+    }
+
+    async fn apply(&self, options: &StepOptions) -> Result<Outcome> {
+        add_dns_for_minikube(options.dry_run)
+    }
+
+    async fn revert(&self, options: &StepOptions) -> Result<Outcome> {
+        remove_dns_for_minikube(self.name(), options.dry_run)
+    }
 }
 
-fn add_dns_data(mut data: String, minikube_ip: String) -> String {
-    data.push_str(LINE_ENDING);
-    data.push_str(LINE_ENDING);
+/// Keeps the entry for k8s.local in krunch's block of the hosts file. An entry outside the block
+/// that krunch is known to have written is moved into it, any other entry outside the block is
+/// left alone.
+pub fn add_dns_for_minikube(dry_run: bool) -> Result<Outcome> {
+    let etc_hosts_path = get_etc_hosts_path()?;
+    let original = fs::read_to_string(&etc_hosts_path)?;
+    let mut state = State::read()?;
+    let mut hosts_file = HostsFile::parse(&original);
+    hosts_file.remove_legacy_lines(legacy_hosts_lines(&state.hosts_lines()));
+
+    let minikube_ip = get_minikube_ip()?;
 
-    let re = regex::Regex::new(&format!(r"(?m)^{}", LINE_ENDING)).unwrap();
-    data = re
-        .replace(
+    let outside = hosts_file
+        .without_block()
+        .state(MINIKUBE_HOST, &minikube_ip);
+    let had_block = hosts_file.has_block();
+    if outside == EntryState::UpToDate && !had_block {
+        return Ok(Outcome::AlreadyDone);
+    }
+
+    state.record(
+        "dns",
+        Change::HostsEntry {
+            line: format!("{}\t{}", minikube_ip, MINIKUBE_HOST),
+        },
+    );
+
+    hosts_file.set_block(&state.hosts_lines());
+    let data = hosts_file.to_string();
+    if data == original {
+        return Ok(Outcome::AlreadyDone);
+    }
+    if dry_run {
+        return Ok(Outcome::Message(get_diff(
+            &etc_hosts_path,
+            &original,
             &data,
-            format!("{}\tk8s.local{}{}", minikube_ip, LINE_ENDING, LINE_ENDING),
-        )
-        .to_string();
+        )));
+    }
+
+    update_etc_hosts(data)?;
+    state.write()?;
 
-    data.trim().to_string()
+    if outside == EntryState::Outdated {
+        Ok(Outcome::Message(format!(
+            "success\nan earlier entry in {} maps {} to another IP, remove it for krunch's entry to take effect",
+            etc_hosts_path.display(),
+            MINIKUBE_HOST
+        )))
+    } else if had_block {
+        Ok(Outcome::Message("minikube ip updated".to_string()))
+    } else {
+        Ok(Outcome::Done)
+    }
 }
 
-fn update_dns_data(mut data: String, minikube_ip: String) -> String {
-    data.push_str(LINE_ENDING);
-    data.push_str(LINE_ENDING);
+/// Matches an entry outside the block that krunch wrote: one recorded in state.json, or the
+/// `<ip>\tk8s.local` of an older krunch version below its `# krunch` comment. The same entry
+/// without either may just as well be the user's, so it isn't matched.
+fn legacy_hosts_lines(recorded: &[String]) -> impl Fn(&[&str]) -> usize + '_ {
+    move |lines| match lines {
+        ["# krunch", line, ..] if is_minikube_entry(line) => 2,
+        [line, ..] if recorded.iter().any(|entry| entry == line) => 1,
+        _ => 0,
+    }
+}
 
-    let re = regex::Regex::new(&format!(r"(?m)^.*k8s.local{}", LINE_ENDING)).unwrap();
-    data = re
-        .replace(&data, format!("{}\tk8s.local{}", minikube_ip, LINE_ENDING))
-        .to_string();
+/// Whether `line` is `<ip>\tk8s.local` with nothing else on it, whichever IP minikube had.
+fn is_minikube_entry(line: &str) -> bool {
+    match line.split('\t').collect::<Vec<_>>()[..] {
+        [ip, host] => host == MINIKUBE_HOST && ip.parse::<IpAddr>().is_ok(),
+        _ => false,
+    }
+}
 
-    data.trim().to_string()
+/// Whether /etc/hosts maps k8s.local to the IP of the running minikube, reading it needs no
+/// admin rights.
+pub fn get_dns_state() -> Result<EntryState> {
+    let data = fs::read_to_string(get_etc_hosts_path()?)?;
+    Ok(HostsFile::parse(&data).state(MINIKUBE_HOST, &get_minikube_ip()?))
 }
 
 fn get_minikube_ip() -> Result<String> {
-    let output = Command::new(get_binary_path(Binary::Minikube)?)
+    let output = Command::new(get_binary_path("minikube")?)
         .arg("ip")
-        .output()
-        .expect("failed to execute process");
+        .output()?;
 
     let ip = handle_output(output)?;
 
     Ok(ip)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::managed_block::{BLOCK_END, BLOCK_START};
+
+    #[test]
+    fn test_adopt_legacy_entry() {
+        let recorded = vec!["192.168.49.2\tk8s.local".to_string()];
+        let legacy = legacy_hosts_lines(&recorded);
+        // written by a krunch version before the block, for an older minikube
+        let original = "127.0.0.1\tlocalhost\n# krunch\n192.168.49.1\tk8s.local\n\n# mine\n";
+
+        let mut hosts_file = HostsFile::parse(original);
+        hosts_file.remove_legacy_lines(&legacy);
+        assert_eq!(
+            hosts_file.state(MINIKUBE_HOST, "192.168.49.3"),
+            EntryState::Missing
+        );
+
+        hosts_file.set_block(&["192.168.49.3\tk8s.local".to_string()]);
+        let data = hosts_file.to_string();
+        assert_eq!(
+            data,
+            format!(
+                "127.0.0.1\tlocalhost\n\n# mine\n{}\n192.168.49.3\tk8s.local\n{}\n",
+                BLOCK_START, BLOCK_END
+            )
+        );
+        assert_eq!(hosts_file.resolve(MINIKUBE_HOST), Some("192.168.49.3"));
+        // the entry inside the block isn't a leftover
+        hosts_file.remove_legacy_lines(&legacy);
+        assert_eq!(hosts_file.to_string(), data);
+
+        // an entry recorded in state.json is krunch's as well
+        let mut hosts_file = HostsFile::parse("127.0.0.1\tlocalhost\n192.168.49.2\tk8s.local\n");
+        hosts_file.remove_legacy_lines(&legacy);
+        assert_eq!(hosts_file.to_string(), "127.0.0.1\tlocalhost\n");
+    }
+
+    #[test]
+    fn test_keep_user_entry() {
+        let recorded = vec!["192.168.49.2\tk8s.local".to_string()];
+        let legacy = legacy_hosts_lines(&recorded);
+
+        // entries the user wrote stay byte for byte, even in the format krunch uses
+        for mine in [
+            "192.168.49.7\tk8s.local\n",
+            "10.0.0.5 k8s.local\n192.168.49.2\tk8s.local registry.local\n",
+            "# mine\n192.168.49.7\tk8s.local\r\n",
+        ] {
+            let mut hosts_file = HostsFile::parse(mine);
+            hosts_file.remove_legacy_lines(&legacy);
+            assert_eq!(hosts_file.to_string(), mine);
+        }
+
+        // and one mapping k8s.local elsewhere is reported instead
+        let hosts_file = HostsFile::parse("192.168.49.7\tk8s.local\n");
+        assert_eq!(
+            hosts_file
+                .without_block()
+                .state(MINIKUBE_HOST, "192.168.49.3"),
+            EntryState::Outdated
+        );
+    }
+}
@@ -1,126 +1,312 @@
-use crate::shared::file_folder_paths::{get_binary_path, get_shell_profile_path, Binary};
-use crate::shared::handle_output;
+use crate::cli_install::bin_folder_to_path::get_path_export;
+use crate::cli_remove::remove_environment_entries::remove_environment_entries;
+use crate::shared::file_folder_paths::{get_binary_path, get_shell_profile_path};
+use crate::shared::managed_block::{get_block, set_profile_lines};
+use crate::shared::state::{record_change, Change, State};
+use crate::shared::steps::{Check, CheckStatus, Outcome, Step, StepOptions};
 use crate::shared::windows_registry::read_from_environment;
+use crate::shared::{handle_output, EntryState};
 use anyhow::Result;
-use std::fs;
+use async_trait::async_trait;
+use std::ops::Range;
 use std::process::Command;
+use std::{env, fs};
 
-pub async fn point_docker_to_minikube() -> Result<()> {
-    if cfg!(target_family = "unix") {
-        point_docker_to_minikube_unix()?;
-    } else if cfg!(target_family = "windows") {
-        point_docker_to_minikube_windows()?;
+/// Points the docker cli to the docker engine inside minikube.
+pub struct DockerStep;
+
+#[async_trait(?Send)]
+impl Step for DockerStep {
+    fn name(&self) -> &'static str {
+        "docker"
+    }
+
+    fn describe(&self) -> &'static str {
+        "point docker cli to minikube"
     }
 
-    Ok(())
+    fn describe_revert(&self) -> Option<&'static str> {
+        Some("deleting docker variables")
+    }
+
+    async fn check(&self) -> Vec<Check> {
+        let label = "docker cli pointed to minikube";
+        match check_docker_env() {
+            Ok(status) => vec![Check::new(label, status)],
+            Err(err) => vec![Check::unavailable(label, err)],
+        }
+    }
+
+    async fn apply(&self, options: &StepOptions) -> Result<Outcome> {
+        point_docker_to_minikube(options.dry_run)
+    }
+
+    async fn revert(&self, options: &StepOptions) -> Result<Outcome> {
+        remove_environment_entries(self.name(), options.dry_run)
+    }
 }
 
-fn point_docker_to_minikube_unix() -> Result<()> {
-    let (docker_tls_verify, docker_host, docker_cert_path, minikube_active_dockerd) =
-        get_docker_env()?;
+fn check_docker_env() -> Result<CheckStatus> {
+    let docker_host = get_minikube_docker_host()?;
+    let remedy = "let krunch point the docker cli to minikube".to_string();
+
+    match docker_env_state(&docker_host)? {
+        EntryState::Missing => Ok(CheckStatus::Problem {
+            description: "DOCKER_HOST is not set up".to_string(),
+            remedy,
+            fixable: true,
+        }),
+        EntryState::Outdated => Ok(CheckStatus::Problem {
+            description: "DOCKER_HOST points to an old minikube IP".to_string(),
+            remedy,
+            fixable: true,
+        }),
+        // the profile is right, but this shell may still have a dead VM's address
+        EntryState::UpToDate => match env::var("DOCKER_HOST") {
+            Ok(current) if current != docker_host => Ok(CheckStatus::Problem {
+                description: format!(
+                    "DOCKER_HOST of this shell is {}, minikube runs docker at {}",
+                    current, docker_host
+                ),
+                remedy: "open a new shell or run `eval $(minikube docker-env)`".to_string(),
+                fixable: false,
+            }),
+            _ => Ok(CheckStatus::Ok("ok".to_string())),
+        },
+    }
+}
 
-    let profile_path = get_shell_profile_path()?;
+pub fn point_docker_to_minikube(dry_run: bool) -> Result<Outcome> {
+    if cfg!(target_family = "windows") {
+        point_docker_to_minikube_windows(dry_run)
+    } else {
+        point_docker_to_minikube_unix(dry_run)
+    }
+}
 
-    let mut data = fs::read_to_string(&profile_path)?;
-    data = data.trim().to_string();
+/// The DOCKER_HOST of the docker engine inside the running minikube.
+pub fn get_minikube_docker_host() -> Result<String> {
+    let (_, docker_host, _, _) = get_docker_env()?;
+    Ok(docker_host)
+}
 
-    if data.contains(&docker_host) {
-        println!("already done");
-    } else if data.contains("export DOCKER_HOST") {
-        let re = regex::Regex::new(r"(?m)^export DOCKER_HOST.*\n").unwrap();
-        data = re
-            .replace(&data, format!("export DOCKER_HOST=\"{}\"\n", docker_host))
-            .to_string();
-        fs::write(profile_path, data)?;
+/// Compares the DOCKER_HOST that krunch's block in the shell profile or, on Windows, the user
+/// environment sets with the one of minikube.
+fn docker_env_state(docker_host: &str) -> Result<EntryState> {
+    let current_docker_host = if cfg!(target_family = "windows") {
+        read_from_environment("DOCKER_HOST").ok()
+    } else {
+        let data = fs::read_to_string(get_shell_profile_path()?)?;
+        get_block(&data).and_then(|lines| {
+            lines.iter().find_map(|line| {
+                line.strip_prefix("export DOCKER_HOST=")
+                    .map(|value| value.trim_matches('"').to_string())
+            })
+        })
+    };
+
+    Ok(match current_docker_host {
+        Some(current) if current == docker_host => EntryState::UpToDate,
+        Some(_) => EntryState::Outdated,
+        None => EntryState::Missing,
+    })
+}
+
+/// Whether krunch set the user variable DOCKER_HOST, and so may update it.
+fn is_docker_host_recorded() -> Result<bool> {
+    Ok(State::read()?
+        .changes("docker")
+        .iter()
+        .any(|change| matches!(change, Change::UserVariable { name, .. } if name == "DOCKER_HOST")))
+}
 
-        println!("minikube IP updated");
+/// Matches the variables krunch versions before the block exported. They followed the `PATH` line
+/// below the `# krunch` comment, without it they may just as well be the user's and are left
+/// alone. The comment and the `PATH` line stay for the path step, which adopts them.
+fn legacy_docker_lines(path_export: &str) -> impl Fn(&[&str]) -> Range<usize> + '_ {
+    move |lines| match lines {
+        ["# krunch", line, rest @ ..] if *line == path_export => {
+            let count = legacy_docker_exports(rest);
+            if count > 0 {
+                2..2 + count
+            } else {
+                0..0
+            }
+        }
+        // the path step already adopted its line
+        ["# krunch", rest @ ..] => match legacy_docker_exports(rest) {
+            0 => 0..0,
+            count => 0..1 + count,
+        },
+        _ => 0..0,
+    }
+}
+
+/// How many of `lines` are the variables krunch versions before the block exported, in their
+/// order and quoting, as opposed to a DOCKER_HOST the user set up.
+pub fn legacy_docker_exports(lines: &[&str]) -> usize {
+    let names = [
+        "DOCKER_TLS_VERIFY",
+        "DOCKER_HOST",
+        "DOCKER_CERT_PATH",
+        "MINIKUBE_ACTIVE_DOCKERD",
+    ];
+    let is_legacy = lines.len() >= names.len()
+        && names.iter().zip(lines).all(|(name, line)| {
+            line.strip_prefix(&format!("export {}=\"", name))
+                .is_some_and(|value| value.ends_with('"'))
+        });
+
+    if is_legacy {
+        names.len()
     } else {
-        data.push_str(format!("\nexport DOCKER_TLS_VERIFY=\"{}\"", docker_tls_verify).as_str());
-        data.push_str(format!("\nexport DOCKER_HOST=\"{}\"", docker_host).as_str());
-        data.push_str(format!("\nexport DOCKER_CERT_PATH=\"{}\"", docker_cert_path).as_str());
-        data.push_str(
-            format!(
-                "\nexport MINIKUBE_ACTIVE_DOCKERD=\"{}\"",
-                minikube_active_dockerd
-            )
-            .as_str(),
-        );
-        data.push_str("\n\n");
+        0
+    }
+}
 
-        fs::write(profile_path, data)?;
+fn point_docker_to_minikube_unix(dry_run: bool) -> Result<Outcome> {
+    let (docker_tls_verify, docker_host, docker_cert_path, minikube_active_dockerd) =
+        get_docker_env()?;
 
-        println!("success");
+    let state = docker_env_state(&docker_host)?;
+    if state == EntryState::UpToDate {
+        return Ok(Outcome::AlreadyDone);
     }
 
-    Ok(())
+    let lines = vec![
+        format!("export DOCKER_TLS_VERIFY=\"{}\"", docker_tls_verify),
+        format!("export DOCKER_HOST=\"{}\"", docker_host),
+        format!("export DOCKER_CERT_PATH=\"{}\"", docker_cert_path),
+        format!(
+            "export MINIKUBE_ACTIVE_DOCKERD=\"{}\"",
+            minikube_active_dockerd
+        ),
+    ];
+
+    // a DOCKER_HOST the user set outside krunch's block stays, the block comes after it
+    let path_export = get_path_export()?;
+    let outcome = set_profile_lines(
+        "docker",
+        &get_shell_profile_path()?,
+        lines,
+        legacy_docker_lines(&path_export),
+        dry_run,
+    )?;
+    match outcome {
+        Outcome::Done if state == EntryState::Outdated => {
+            Ok(Outcome::Message("minikube IP updated".to_string()))
+        }
+        outcome => Ok(outcome),
+    }
 }
 
-fn point_docker_to_minikube_windows() -> Result<()> {
+fn point_docker_to_minikube_windows(dry_run: bool) -> Result<Outcome> {
     let (docker_tls_verify, docker_host, docker_cert_path, minikube_active_dockerd) =
         get_docker_env()?;
 
-    let current_docker_host = read_from_environment("DOCKER_HOST");
+    let state = docker_env_state(&docker_host)?;
 
-    if let Ok(current_docker_host) = current_docker_host {
-        if docker_host == current_docker_host {
-            println!("already done");
+    // a DOCKER_HOST krunch didn't set is the user's, e.g. for another docker daemon
+    if state == EntryState::Outdated && !is_docker_host_recorded()? {
+        return Ok(Outcome::Message(format!(
+            "the user variable DOCKER_HOST points to another docker, which krunch didn't set, change it to {} to use minikube's",
+            docker_host
+        )));
+    }
+
+    if dry_run && state != EntryState::UpToDate {
+        let mut plan = "would set the user variables".to_string();
+        if state == EntryState::Missing {
+            plan.push_str(&format!("\n  DOCKER_TLS_VERIFY={}", docker_tls_verify));
+        }
+        plan.push_str(&format!("\n  DOCKER_HOST={}", docker_host));
+        if state == EntryState::Missing {
+            plan.push_str(&format!("\n  DOCKER_CERT_PATH={}", docker_cert_path));
+            plan.push_str(&format!(
+                "\n  MINIKUBE_ACTIVE_DOCKERD={}",
+                minikube_active_dockerd
+            ));
+        }
+        return Ok(Outcome::Message(plan));
+    }
+
+    if state != EntryState::Missing {
+        if state == EntryState::UpToDate {
+            Ok(Outcome::AlreadyDone)
         } else {
             let output = Command::new("SETX")
                 .arg("DOCKER_HOST")
-                .arg(docker_host)
-                .output()
-                .expect("failed to execute process");
+                .arg(&docker_host)
+                .output()?;
 
             handle_output(output)?;
 
-            println!("minikube IP updated");
+            let mut state = State::read()?;
+            state.record(
+                "docker",
+                Change::UserVariable {
+                    name: "DOCKER_HOST".to_string(),
+                    value: docker_host,
+                },
+            );
+            state.write()?;
+
+            Ok(Outcome::Message("minikube IP updated".to_string()))
         }
     } else {
         let output = Command::new("SETX")
             .arg("DOCKER_TLS_VERIFY")
-            .arg(docker_tls_verify)
-            .output()
-            .expect("failed to execute process");
+            .arg(&docker_tls_verify)
+            .output()?;
 
         handle_output(output)?;
 
         let output = Command::new("SETX")
             .arg("DOCKER_HOST")
-            .arg(docker_host)
-            .output()
-            .expect("failed to execute process");
+            .arg(&docker_host)
+            .output()?;
 
         handle_output(output)?;
 
         let output = Command::new("SETX")
             .arg("DOCKER_CERT_PATH")
-            .arg(docker_cert_path)
-            .output()
-            .expect("failed to execute process");
+            .arg(&docker_cert_path)
+            .output()?;
 
         handle_output(output)?;
 
         let output = Command::new("SETX")
             .arg("MINIKUBE_ACTIVE_DOCKERD")
-            .arg(minikube_active_dockerd)
-            .output()
-            .expect("failed to execute process");
+            .arg(&minikube_active_dockerd)
+            .output()?;
 
         handle_output(output)?;
 
-        println!("success");
-    }
+        for (name, value) in [
+            ("DOCKER_TLS_VERIFY", docker_tls_verify),
+            ("DOCKER_HOST", docker_host),
+            ("DOCKER_CERT_PATH", docker_cert_path),
+            ("MINIKUBE_ACTIVE_DOCKERD", minikube_active_dockerd),
+        ] {
+            record_change(
+                "docker",
+                Change::UserVariable {
+                    name: name.to_string(),
+                    value,
+                },
+            )?;
+        }
 
-    Ok(())
+        Ok(Outcome::Done)
+    }
 }
 
 fn get_docker_env() -> Result<(String, String, String, String)> {
-    let output = Command::new(get_binary_path(Binary::Minikube)?)
+    let output = Command::new(get_binary_path("minikube")?)
         .arg("docker-env")
         .arg("--shell")
         .arg("bash")
-        .output()
-        .expect("failed to execute process");
+        .output()?;
 
     let docker_env = handle_output(output)?;
 
@@ -175,4 +361,33 @@ mod tests {
         assert_eq!(docker_cert_path, "/home/timo/.minikube/certs");
         assert_eq!(minikube_active_dockerd, "minikube");
     }
+
+    #[test]
+    fn test_legacy_docker_lines() {
+        use crate::shared::managed_block::remove_legacy_lines;
+
+        let path = "if [[ \":$PATH:\" != *\":/home/me/.krunch/bin:\"* ]]; then export PATH=\"/home/me/.krunch/bin:$PATH\"; fi";
+        let exports = "export DOCKER_TLS_VERIFY=\"1\"\n\
+            export DOCKER_HOST=\"tcp://192.168.49.2:2376\"\n\
+            export DOCKER_CERT_PATH=\"/home/me/.minikube/certs\"\n\
+            export MINIKUBE_ACTIVE_DOCKERD=\"minikube\"\n";
+        let legacy = legacy_docker_lines(path);
+        // what the path step of an older krunch wrote, with the docker step's lines after it
+        let original = format!("alias ll='ls -l'\n\n# krunch\n{}\n{}\n", path, exports);
+
+        // the comment and PATH line stay for the path step
+        let data = remove_legacy_lines(&original, &legacy);
+        assert_eq!(data, format!("alias ll='ls -l'\n\n# krunch\n{}\n\n", path));
+
+        // or the path step adopted its line first and left the comment
+        let data = remove_legacy_lines(
+            &format!("alias ll='ls -l'\n\n# krunch\n{}\n", exports),
+            &legacy,
+        );
+        assert_eq!(data, "alias ll='ls -l'\n\n\n");
+
+        // the same variables without the comment are the user's
+        let mine = format!("alias ll='ls -l'\n{}", exports);
+        assert_eq!(remove_legacy_lines(&mine, &legacy), mine);
+    }
 }
@@ -1,139 +1,753 @@
-use crate::cli_install::download_urls::get_necessary_downloads;
-use crate::cli_install::get_versions::create_default_config_if_needed;
-use crate::shared::file_folder_paths::{get_bin_folder, get_buildx_folder};
+use crate::cli_install::checksums::{
+    get_expected_digest, get_pinned_digest, record_digest, verify_digest,
+};
+use crate::cli_install::download_urls::{
+    get_all_downloads, get_configured_tools, get_necessary_downloads, Download,
+};
+use crate::cli_install::get_versions::{
+    create_default_config_if_needed, get_default_versions, pin_versions,
+};
+use crate::cli_remove::remove_binaries::remove_binaries;
+use crate::shared::bundle::Bundle;
+use crate::shared::cache::Cache;
+use crate::shared::config::{get_config, KrunchConfig};
+use crate::shared::file_folder_paths::{get_bin_folder, get_config_file_path, get_lock_file_path};
+use crate::shared::http::{fetch_to_file, get_http_client, with_retries};
+use crate::shared::lock::Lockfile;
+use crate::shared::mirrors::get_candidate_urls;
+use crate::shared::sha256_of_file;
+use crate::shared::shims::{create_shim, has_shim, resolve_version};
+use crate::shared::steps::{Check, CheckStatus, Outcome, Step, StepOptions};
+use crate::shared::tools::{save_definition, Archive, Platform, Tool};
 use anyhow::{anyhow, Result};
+use async_trait::async_trait;
 use flate2::read::GzDecoder;
+use futures::stream;
 use futures_util::StreamExt;
-use indicatif::{ProgressBar, ProgressStyle};
-use reqwest::Url;
-use std::cmp::min;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use reqwest::Client;
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
 use std::fs::File;
-use std::io::prelude::*;
-use std::path::PathBuf;
-use std::{fs, io};
-use tar::Archive;
-use tempfile::{Builder, TempDir};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tar::Archive as TarArchive;
+use tempfile::{Builder, NamedTempFile, TempDir};
 use terminal_size::terminal_size;
 use walkdir::{DirEntry, WalkDir};
 
-pub async fn download_all() -> Result<()> {
+/// Downloads the configured tools and puts their shims into the bin folder.
+pub struct ToolsStep;
+
+#[async_trait(?Send)]
+impl Step for ToolsStep {
+    fn name(&self) -> &'static str {
+        "tools"
+    }
+
+    fn describe(&self) -> &'static str {
+        "downloading tools"
+    }
+
+    fn describe_revert(&self) -> Option<&'static str> {
+        Some("deleting downloaded files")
+    }
+
+    fn requires_minikube(&self) -> bool {
+        false
+    }
+
+    async fn check(&self) -> Vec<Check> {
+        let configured = match get_config().and_then(|config| get_configured_tools(&config)) {
+            Ok(configured) => configured,
+            Err(err) => return vec![Check::new("tools", CheckStatus::Error(err))],
+        };
+
+        let mut checks = vec![];
+        for (tool, spec) in &configured {
+            let status = check_tool(tool, spec)
+                .await
+                .unwrap_or_else(CheckStatus::Error);
+            checks.push(Check::new(&format!("tool {}", tool.name), status));
+        }
+
+        checks
+    }
+
+    async fn apply(&self, options: &StepOptions) -> Result<Outcome> {
+        let platform = match options.platform {
+            Some(platform) => platform,
+            None => Platform::current()?,
+        };
+
+        download_all(platform, options.bundle, options.locked, options.dry_run).await
+    }
+
+    async fn revert(&self, options: &StepOptions) -> Result<Outcome> {
+        remove_binaries(options.dry_run)
+    }
+}
+
+async fn check_tool(tool: &Tool, spec: &str) -> Result<CheckStatus> {
+    let platform = Platform::current()?;
+    let download = |description: String| CheckStatus::Problem {
+        description,
+        remedy: "download it with `krunch install`".to_string(),
+        fixable: true,
+    };
+
+    let version = match resolve_version(tool) {
+        Ok(version) => version,
+        Err(_) => return Ok(download(format!("missing (configured {})", spec))),
+    };
+    let version_path = tool.version_path(&version, &platform)?;
+    if !version_path.exists() {
+        return Ok(download(format!(
+            "{} missing (configured {})",
+            version, spec
+        )));
+    }
+    // a probe that finds no version in the output is inconclusive, not a problem
+    if let Some(probe) = &tool.version {
+        match probe.run(&version_path).await {
+            Ok(Some(reported))
+                if reported.trim_start_matches('v') != version.trim_start_matches('v') =>
+            {
+                return Ok(CheckStatus::Problem {
+                    description: format!(
+                        "{} installed, but the binary reports {}",
+                        version, reported
+                    ),
+                    remedy: format!(
+                        "delete {} and download it again with `krunch install`",
+                        version_path.display()
+                    ),
+                    fixable: false,
+                })
+            }
+            Ok(_) => {}
+            Err(err) => {
+                return Ok(CheckStatus::Problem {
+                    description: format!("{} installed, but {}", version, err),
+                    remedy: format!(
+                        "delete {} and download it again with `krunch install`",
+                        version_path.display()
+                    ),
+                    fixable: false,
+                })
+            }
+        }
+    }
+    if !has_shim(tool)? {
+        return Ok(download(format!(
+            "{} installed, but its shim is missing or links to another krunch",
+            version
+        )));
+    }
+
+    // tools installed elsewhere, like docker plugins, aren't run through PATH
+    let bin_folder = get_bin_folder()?;
+    let path = env::var_os("PATH").unwrap_or_default();
+    if tool.folder()? == bin_folder {
+        if let Some(found) = find_in_path(&path, &tool.file_name(&platform)) {
+            let folder = found.parent().unwrap_or(&found);
+            if folder != bin_folder {
+                return Ok(CheckStatus::Problem {
+                    description: format!("{} comes first in PATH", found.display()),
+                    remedy: format!(
+                        "put {} before {} in PATH, or uninstall the other {}",
+                        bin_folder.display(),
+                        folder.display(),
+                        tool.name
+                    ),
+                    fixable: false,
+                });
+            }
+        }
+    }
+
+    Ok(CheckStatus::Ok(if version == spec {
+        version
+    } else {
+        format!("{} (configured {})", version, spec)
+    }))
+}
+
+/// Returns the first file called `file_name` in the folders of a PATH-like variable.
+fn find_in_path(path: &OsStr, file_name: &str) -> Option<PathBuf> {
+    env::split_paths(path)
+        .map(|folder| folder.join(file_name))
+        .find(|candidate| candidate.is_file())
+}
+
+pub async fn download_all(
+    platform: Platform,
+    bundle: Option<&Path>,
+    locked: bool,
+    dry_run: bool,
+) -> Result<Outcome> {
+    // the bundle's versions go into the config instead of the default ones
+    if let Some(bundle) = bundle {
+        return install_from_bundle(bundle, platform, dry_run);
+    }
+
+    if dry_run && !get_config_file_path()?.exists() {
+        let mut plan = format!(
+            "would create {} with the default versions and download",
+            get_config_file_path()?.display()
+        );
+        for (name, version) in get_default_versions() {
+            plan.push_str(&format!("\n  {} {}", name, version));
+        }
+        return Ok(Outcome::Message(plan));
+    }
     create_default_config_if_needed()?;
-    let downloads = get_necessary_downloads()?;
 
-    if downloads.is_empty() {
-        println!("already done")
+    let lock = Lockfile::read()?;
+    if locked {
+        let lock = lock.as_ref().ok_or(anyhow!(
+            "{} does not exist, run `krunch lock` to create it",
+            get_lock_file_path()?.display()
+        ))?;
+        lock.check(&get_configured_tools(&get_config()?)?, &platform)?;
+    }
+
+    let downloads = get_necessary_downloads(platform, lock.as_ref()).await?;
+
+    if dry_run {
+        let mut missing_shims = vec![];
+        for download in get_all_downloads(platform, lock.as_ref()).await? {
+            if !has_shim(&download.tool)? {
+                missing_shims.push(download.tool.install_path()?);
+            }
+        }
+        return get_download_plan(&downloads, &missing_shims);
+    }
+
+    let result = if downloads.is_empty() {
+        Ok(Outcome::AlreadyDone)
     } else {
-        println!("{:?}", downloads)
+        // ends the step's line, the progress bars and results follow below it
+        println!(
+            "{}",
+            get_version_list(downloads.iter().map(|d| (&d.tool, d.version.as_str())))
+        );
+
+        fetch_all(&get_config()?, &downloads, |download, artifact| {
+            install_artifact(
+                artifact,
+                &download.tool,
+                &download.version,
+                &download.platform,
+            )
+        })
+        .await
+        .map(|()| Outcome::Reported)
+    };
+
+    // shims of tools installed earlier may be missing or still be a binary of an older krunch,
+    // and a failed download mustn't leave the tools that did install without one
+    for download in get_all_downloads(platform, lock.as_ref()).await? {
+        if download
+            .tool
+            .version_path(&download.version, &download.platform)?
+            .exists()
+        {
+            create_shim(&download.tool, &download.platform)?;
+        }
     }
 
+    result
+}
+
+/// Lists what is about to be installed, e.g. `kubectl 1.28.3, helm 3.14.4`.
+fn get_version_list<'a>(tools: impl Iterator<Item = (&'a Tool, &'a str)>) -> String {
+    tools
+        .map(|(tool, version)| format!("{} {}", tool.name, version))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn get_download_plan(downloads: &[Download], missing_shims: &[PathBuf]) -> Result<Outcome> {
+    if downloads.is_empty() && missing_shims.is_empty() {
+        return Ok(Outcome::AlreadyDone);
+    }
+
+    let mut plan = "would install".to_string();
     for download in downloads {
-        print!("{:<35}", format!("downloading {}", &download.target));
-        io::stdout().flush().unwrap();
-        download_file(download.source, download.target.as_str()).await?;
-        println!("{:<35}success", format!("downloading {}", &download.target));
+        plan.push_str(&format!(
+            "\n  {} {} downloaded from {} to {}",
+            download.tool.name,
+            download.version,
+            download.source,
+            download
+                .tool
+                .version_path(&download.version, &download.platform)?
+                .display()
+        ));
+    }
+    for shim in missing_shims {
+        plan.push_str(&format!("\n  shim {}", shim.display()));
+    }
+
+    Ok(Outcome::Message(plan))
+}
+
+/// Fetches all downloads in parallel, from the cache where possible, and passes each verified
+/// artifact to `handle`. Reports the outcome per download and fails if any of them failed.
+pub async fn fetch_all(
+    config: &KrunchConfig,
+    downloads: &[Download],
+    handle: impl Fn(&Download, &Path) -> Result<()>,
+) -> Result<()> {
+    let client = get_http_client(&config.download)?;
+    let multi_progress = MultiProgress::new();
+    // the same file name is downloaded for several platforms when locking
+    let show_platform = downloads
+        .iter()
+        .any(|d| d.platform != downloads[0].platform);
+    let results: Vec<(String, Result<DownloadOutcome>)> = stream::iter(downloads)
+        .map(|download| {
+            let target = if show_platform {
+                format!("{} ({})", download.target(), download.platform)
+            } else {
+                download.target()
+            };
+            let pb = multi_progress.add(get_progress_bar(&target));
+            let (client, handle) = (&client, &handle);
+            async move {
+                let result = fetch_file(download, client, config, &pb, handle).await;
+                pb.finish_and_clear();
+                (target, result)
+            }
+        })
+        .buffer_unordered(config.parallel_downloads.max(1))
+        .collect()
+        .await;
+
+    let mut failed = vec![];
+    for (target, result) in results {
+        let status = match result {
+            Ok(DownloadOutcome::Verified) => "success".to_string(),
+            Ok(DownloadOutcome::Unverified) => {
+                "success (unverified, no checksum available)".to_string()
+            }
+            Ok(DownloadOutcome::Recorded) => "success (checksum recorded on first use)".to_string(),
+            Ok(DownloadOutcome::Cached) => "success (from cache)".to_string(),
+            Ok(DownloadOutcome::CachedRecorded) => {
+                "success (from cache, checksum recorded on first use)".to_string()
+            }
+            Ok(DownloadOutcome::CachedUnverified) => {
+                "success (from cache, unverified, no checksum available)".to_string()
+            }
+            Err(err) => {
+                failed.push(target.clone());
+                format!("failed: {}", err)
+            }
+        };
+        println!("{:<34} {}", format!("downloading {}", target), status);
+    }
+
+    if !failed.is_empty() {
+        return Err(anyhow!("failed to download {}", failed.join(", ")));
     }
 
     Ok(())
 }
 
-async fn download_file(url: Url, target_name: &str) -> Result<()> {
+enum DownloadOutcome {
+    Verified,
+    Unverified,
+    /// Had no checksum, its digest was recorded for the next download.
+    Recorded,
+    Cached,
+    CachedUnverified,
+    CachedRecorded,
+}
+
+/// Takes a single artifact from the cache, or downloads, verifies and caches it first.
+async fn fetch_file(
+    download: &Download,
+    client: &Client,
+    config: &KrunchConfig,
+    pb: &ProgressBar,
+    handle: &impl Fn(&Download, &Path) -> Result<()>,
+) -> Result<DownloadOutcome> {
+    let url = &download.source;
+    let cache = Cache::open(&config.cache)?;
+
+    let cached = cache.lookup(url.as_str(), get_pinned_digest(download)?.as_deref())?;
+    if let Some(cached) = cached.as_ref().filter(|cached| cached.verified) {
+        handle(download, &cached.path)?;
+        return Ok(DownloadOutcome::Cached);
+    }
+
+    let expected_digest = get_expected_digest(download, client, config).await?;
+    // a file cached without a checksum is used once one confirms it, and downloaded again if
+    // the checksum doesn't match
+    if let Some(cached) = cached {
+        match &expected_digest {
+            Some(expected) if *expected != cached.sha256 => {}
+            Some(_) => {
+                cache.insert(url.as_str(), &cached.path, true)?;
+                handle(download, &cached.path)?;
+                return Ok(DownloadOutcome::Cached);
+            }
+            None if config.record_checksums => {
+                record_digest(url.as_str(), &cached.sha256)?;
+                handle(download, &cached.path)?;
+                return Ok(DownloadOutcome::CachedRecorded);
+            }
+            None => {
+                handle(download, &cached.path)?;
+                return Ok(DownloadOutcome::CachedUnverified);
+            }
+        }
+    }
     let tmp_dir = Builder::new().tempdir()?;
-    let tmp_file_name = url.path_segments().unwrap().last().unwrap();
-    let tmp_file_path = tmp_dir.path().join(tmp_file_name);
-    let mut tmp_file = File::create(&tmp_file_path)?;
+    let tmp_file_path = tmp_dir.path().join(download.artifact_name());
 
-    let response = reqwest::get(url.clone()).await?;
-    let total_size = response
-        .content_length()
-        .ok_or(anyhow!("failed to get content length from '{}'", &url))?;
-    let pb = get_progress_bar(total_size, target_name);
+    let on_progress = |downloaded: u64, total_size: Option<u64>| {
+        set_progress_style(pb, total_size);
+        pb.set_position(downloaded);
+    };
+    let on_retry = |attempt: u32, err: &anyhow::Error| {
+        pb.println(format!(
+            "retrying {} ({}/{}): {}",
+            download.target(),
+            attempt,
+            config.download.retries,
+            err
+        ));
+    };
+    let candidates = get_candidate_urls(
+        &config.mirrors,
+        config.upstream_fallback,
+        &download.tool.name,
+        url,
+    )?;
+    let mut errors = vec![];
+    for candidate in &candidates {
+        let result = with_retries(
+            &config.download,
+            || {
+                fetch_to_file(
+                    client,
+                    &config.download,
+                    candidate,
+                    &tmp_file_path,
+                    &on_progress,
+                )
+            },
+            on_retry,
+        )
+        .await;
 
-    let mut downloaded: u64 = 0;
-    let mut stream = response.bytes_stream();
+        // a mirror with a damaged or tampered copy is skipped like one that is down
+        let result = result.and_then(|()| match &expected_digest {
+            Some(expected_digest) => verify_digest(&tmp_file_path, expected_digest, download),
+            None => Ok(()),
+        });
+        match result {
+            Ok(()) => break,
+            Err(err) => {
+                // a partial download from one mirror is not resumed from another
+                fs::remove_file(&tmp_file_path).unwrap_or(());
+                errors.push(format!("{}: {}", candidate, err));
+            }
+        }
+    }
 
-    while let Some(item) = stream.next().await {
-        let chunk = item?;
-        tmp_file.write_all(&chunk)?;
-        let new = min(downloaded + (chunk.len() as u64), total_size);
-        downloaded = new;
-        pb.set_position(new);
+    if errors.len() == candidates.len() {
+        return Err(anyhow!(errors.join(", ")));
     }
 
-    handle_tmp_file(tmp_file_path, target_name)?;
+    cache.insert(url.as_str(), &tmp_file_path, expected_digest.is_some())?;
+    let recorded = expected_digest.is_none() && config.record_checksums;
+    if recorded {
+        record_digest(url.as_str(), &sha256_of_file(&tmp_file_path)?)?;
+    }
+    handle(download, &tmp_file_path)?;
 
-    drop(tmp_file);
     tmp_dir.close()?;
 
-    Ok(())
+    match expected_digest {
+        Some(_) => Ok(DownloadOutcome::Verified),
+        None if recorded => Ok(DownloadOutcome::Recorded),
+        None => Ok(DownloadOutcome::Unverified),
+    }
 }
 
-fn handle_tmp_file(tmp_file_path: PathBuf, target_name: &str) -> Result<()> {
-    let target_path = if target_name.starts_with("docker-buildx") {
-        let buildx_folder = get_buildx_folder()?;
-        fs::create_dir_all(&buildx_folder)?;
-        buildx_folder.join(target_name)
-    } else {
-        let bin_folder = get_bin_folder()?;
-        fs::create_dir_all(&bin_folder)?;
-        bin_folder.join(target_name)
-    };
+/// Installs the tools of an offline bundle without any network access.
+fn install_from_bundle(path: &Path, platform: Platform, dry_run: bool) -> Result<Outcome> {
+    let bundle = Bundle::open(path)?;
 
-    if tmp_file_path.to_str().unwrap().ends_with(".tar.gz")
-        || tmp_file_path.to_str().unwrap().ends_with(".tgz")
-    {
-        let tar_gz = File::open(&tmp_file_path)?;
-        let tar = GzDecoder::new(tar_gz);
-        let tmp_dir = Builder::new().tempdir()?;
-        Archive::new(tar).unpack(&tmp_dir)?;
-        find_and_copy_file(tmp_dir, target_name, &target_path)?;
-    } else if tmp_file_path.to_str().unwrap().ends_with(".zip") {
-        let zip = File::open(&tmp_file_path)?;
-        let tmp_dir = Builder::new().tempdir()?;
-        zip::ZipArchive::new(zip)?.extract(&tmp_dir)?;
-        find_and_copy_file(tmp_dir, target_name, &target_path)?;
-    } else {
-        fs::copy(tmp_file_path, &target_path)?;
+    if bundle.manifest.platform != platform {
+        return Err(anyhow!(
+            "bundle was created for {}, but tools for {} are needed",
+            bundle.manifest.platform,
+            platform
+        ));
+    }
+
+    let versions: Vec<(String, String)> = bundle
+        .manifest
+        .tools
+        .iter()
+        .map(|bundled| (bundled.name.clone(), bundled.version.clone()))
+        .collect();
+    let config_path = get_config_file_path()?;
+
+    let mut necessary = vec![];
+    for bundled in &bundle.manifest.tools {
+        let tool = bundled.tool();
+        if !tool.version_path(&bundled.version, &platform)?.exists() {
+            necessary.push((bundled, tool));
+        }
+    }
+
+    if dry_run {
+        let pinned = pin_versions(&config_path, &versions, true)?;
+        if necessary.is_empty() && pinned.is_empty() {
+            return Ok(Outcome::AlreadyDone);
+        }
+        let mut plan = format!("would install from {}", path.display());
+        for (bundled, tool) in &necessary {
+            plan.push_str(&format!(
+                "\n  {} {} to {}",
+                tool.name,
+                bundled.version,
+                tool.version_path(&bundled.version, &platform)?.display()
+            ));
+        }
+        for (name, version) in versions.iter().filter(|(name, _)| pinned.contains(name)) {
+            plan.push_str(&format!(
+                "\n  set {} to {} in {}",
+                name,
+                version,
+                config_path.display()
+            ));
+        }
+        return Ok(Outcome::Message(plan));
+    }
+
+    // a bundle with a broken artifact installs nothing, not even the shims
+    let mut artifacts = vec![];
+    for (bundled, tool) in &necessary {
+        artifacts.push((bundle.verified_artifact(bundled)?, bundled, tool));
+    }
+
+    if !artifacts.is_empty() {
+        println!(
+            "{}",
+            get_version_list(
+                necessary
+                    .iter()
+                    .map(|(bundled, tool)| (tool, bundled.version.as_str()))
+            )
+        );
+    }
+
+    for (artifact, bundled, tool) in artifacts {
+        install_artifact(&artifact, tool, &bundled.version, &platform)?;
+        println!(
+            "{:<35}success",
+            format!("installing {}", tool.file_name(&platform))
+        );
+    }
+
+    for bundled in &bundle.manifest.tools {
+        let tool = bundled.tool();
+        save_definition(&tool)?;
+        create_shim(&tool, &platform)?;
+    }
+    let pinned = pin_versions(&config_path, &versions, false)?;
+    let pinned = (!pinned.is_empty()).then(|| {
+        format!(
+            "set the versions of {} in {}",
+            pinned.join(", "),
+            config_path.display()
+        )
+    });
+
+    Ok(match pinned {
+        Some(pinned) if necessary.is_empty() => Outcome::Message(pinned),
+        Some(pinned) => {
+            println!("{:<35}{}", "updating config", pinned);
+            Outcome::Reported
+        }
+        None if necessary.is_empty() => Outcome::AlreadyDone,
+        None => Outcome::Reported,
+    })
+}
+
+/// Unpacks a tool into its version folder, the tool's shim then runs it. The binary is written
+/// next to its final path and renamed into place, so a failed install leaves no half-written
+/// binary behind, and no version folder that shims would take for an installed version.
+fn install_artifact(
+    artifact: &Path,
+    tool: &Tool,
+    version: &str,
+    platform: &Platform,
+) -> Result<()> {
+    let target_path = tool.version_path(version, platform)?;
+    let folder = target_path
+        .parent()
+        .ok_or(anyhow!("invalid install path {}", target_path.display()))?;
+    let created = !folder.exists();
+    fs::create_dir_all(folder)?;
+
+    let result = unpack_binary(artifact, tool, platform, folder).and_then(|tmp_file| {
+        tmp_file.persist(&target_path)?;
+        Ok(())
+    });
+    if result.is_err() && created {
+        fs::remove_dir_all(folder)?;
+    }
+
+    result
+}
+
+/// Writes the tool's binary from the artifact to a temporary file in `folder`.
+fn unpack_binary(
+    artifact: &Path,
+    tool: &Tool,
+    platform: &Platform,
+    folder: &Path,
+) -> Result<NamedTempFile> {
+    let tmp_file = NamedTempFile::new_in(folder)?;
+    let binary = tool.binary_in_archive(platform);
+
+    match tool.archive(platform) {
+        Archive::TarGz => {
+            let tar_gz = File::open(artifact)?;
+            let tar = GzDecoder::new(tar_gz);
+            let tmp_dir = Builder::new().tempdir()?;
+            TarArchive::new(tar).unpack(&tmp_dir)?;
+            find_and_copy_file(tmp_dir, &binary, tmp_file.path())?;
+        }
+        Archive::Zip => {
+            let zip = File::open(artifact)?;
+            let tmp_dir = Builder::new().tempdir()?;
+            zip::ZipArchive::new(zip)?.extract(&tmp_dir)?;
+            find_and_copy_file(tmp_dir, &binary, tmp_file.path())?;
+        }
+        Archive::None => {
+            fs::copy(artifact, tmp_file.path())?;
+        }
     }
 
     #[cfg(target_family = "unix")]
     {
         use std::os::unix::fs::PermissionsExt;
-        fs::set_permissions(target_path, fs::Permissions::from_mode(0o755))?;
+        fs::set_permissions(tmp_file.path(), fs::Permissions::from_mode(0o755))?;
     }
 
-    Ok(())
+    Ok(tmp_file)
 }
 
-fn find_and_copy_file(dir: TempDir, to_find: &str, target_path: &PathBuf) -> Result<()> {
+fn find_and_copy_file(dir: TempDir, to_find: &str, target_path: &Path) -> Result<()> {
     for file in WalkDir::new(dir.path())
         .into_iter()
         .filter_map(Result::ok)
         .filter(|e: &DirEntry| e.file_type().is_file())
     {
-        if file.file_name() == to_find {
+        if file.path().ends_with(to_find) {
             fs::copy(file.path(), target_path)?;
+            return Ok(());
         }
     }
 
-    Ok(())
+    Err(anyhow!(
+        "failed to find '{}' in the downloaded archive",
+        to_find
+    ))
 }
 
-fn get_progress_bar(total_size: u64, target_name: &str) -> ProgressBar {
-    let term_width = terminal_size().unwrap().0 .0 as usize;
+/// Starts out as a spinner, which is kept for downloads of unknown size.
+fn get_progress_bar(target_name: &str) -> ProgressBar {
+    let pb = ProgressBar::new_spinner();
+    pb.set_style(
+        ProgressStyle::with_template("{msg}{spinner} {bytes} ({bytes_per_sec})")
+            .expect("invalid progress bar template"),
+    );
+    pb.set_message(format!("{:<34} ", format!("downloading {}", target_name)));
+    pb.enable_steady_tick(Duration::from_millis(100));
+
+    pb
+}
 
-    let style = match term_width {
-        0..=100 => "\r{msg}{bytes}/{total_bytes}".to_string(),
-        _ => "\r{msg}[{bar:30.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec})".to_string(),
+/// Turns the spinner into a bar once the total size is known.
+fn set_progress_style(pb: &ProgressBar, total_size: Option<u64>) {
+    let total_size = match total_size {
+        Some(total_size) if pb.length() != Some(total_size) => total_size,
+        _ => return,
     };
 
-    let pb = ProgressBar::new(total_size);
+    let term_width = terminal_size().map(|(width, _)| width.0).unwrap_or(0);
+
+    let template = match term_width {
+        0..=100 => "{msg}{bytes}/{total_bytes}",
+        _ => "{msg}[{bar:30.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec})",
+    };
+
+    pb.disable_steady_tick();
     pb.set_style(
-        ProgressStyle::default_bar()
-            .template(&style)
+        ProgressStyle::with_template(template)
+            .expect("invalid progress bar template")
             .progress_chars("#>-"),
     );
+    pb.set_length(total_size);
+}
 
-    pb.set_message(&format!("{:<35}", format!("downloading {}", target_name)));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::tools::get_builtin_tools;
+    use std::str::FromStr;
+    use tempfile::tempdir;
 
-    pb
+    #[test]
+    fn test_find_in_path() {
+        let first = tempdir().unwrap();
+        let second = tempdir().unwrap();
+        fs::write(second.path().join("kubectl"), "").unwrap();
+        fs::create_dir(first.path().join("helm")).unwrap();
+        fs::write(second.path().join("helm"), "").unwrap();
+
+        let path = env::join_paths([first.path(), second.path()]).unwrap();
+
+        assert_eq!(
+            find_in_path(&path, "kubectl").unwrap(),
+            second.path().join("kubectl")
+        );
+        // folders named like the tool are not executables
+        assert_eq!(
+            find_in_path(&path, "helm").unwrap(),
+            second.path().join("helm")
+        );
+        assert!(find_in_path(&path, "k9s").is_none());
+    }
+
+    #[test]
+    fn test_unpack_binary_leaves_nothing_on_failure() {
+        let folder = tempdir().unwrap();
+        let artifact = folder.path().join("artifact");
+        fs::write(&artifact, "not an archive").unwrap();
+        let tools = get_builtin_tools().unwrap();
+        let linux = Platform::from_str("linux/amd64").unwrap();
+        let version_folder = folder.path().join("1.0.0");
+        fs::create_dir(&version_folder).unwrap();
+
+        assert!(unpack_binary(&artifact, &tools["helm"], &linux, &version_folder).is_err());
+        assert_eq!(fs::read_dir(&version_folder).unwrap().count(), 0);
+
+        let tmp_file =
+            unpack_binary(&artifact, &tools["kubectl"], &linux, &version_folder).unwrap();
+        assert_eq!(
+            fs::read_to_string(tmp_file.path()).unwrap(),
+            "not an archive"
+        );
+    }
 }
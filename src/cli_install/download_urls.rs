@@ -1,305 +1,183 @@
-use crate::cli_install::get_versions::{get_actual_versions, get_expected_versions};
+use crate::cli_install::checksums::get_recorded_digest;
+use crate::shared::config::{get_config, KrunchConfig};
+use crate::shared::file_folder_paths::get_config_file_path;
+use crate::shared::http::get_http_client;
+use crate::shared::lock::Lockfile;
+use crate::shared::releases::{resolve_release, VersionSpec};
+use crate::shared::tools::{get_tools, Platform, Tool};
 use anyhow::{anyhow, Result};
 use reqwest::Url;
 use std::fmt;
 
 pub struct Download {
-    pub target: String,
+    pub tool: Tool,
+    pub version: String,
+    pub platform: Platform,
     pub source: Url,
+    pub checksum: Option<Url>,
+    pub sha256: Option<String>,
 }
 
-impl fmt::Debug for Download {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.target)
+impl Download {
+    pub fn target(&self) -> String {
+        self.tool.file_name(&self.platform)
     }
-}
 
-enum TargetOs {
-    Windows,
-    MacOs,
-    Linux,
+    /// File name of the downloaded artifact, taken from the last segment of the url.
+    pub fn artifact_name(&self) -> String {
+        self.source
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .filter(|segment| !segment.is_empty())
+            .unwrap_or(&self.tool.name)
+            .to_string()
+    }
 }
 
-enum TargetArch {
-    Amd64,
-    Arm64,
+impl fmt::Debug for Download {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.target())
+    }
 }
 
-pub fn get_necessary_downloads() -> Result<Vec<Download>> {
-    let os;
-    let arch;
-    let ext_str;
-
-    if cfg!(target_os = "windows") && cfg!(target_arch = "x86_64") {
-        os = TargetOs::Windows;
-        arch = TargetArch::Amd64;
-        ext_str = ".exe"
-    } else if cfg!(target_os = "macos") && cfg!(target_arch = "x86_64") {
-        os = TargetOs::MacOs;
-        arch = TargetArch::Amd64;
-        ext_str = ""
-    } else if cfg!(target_os = "macos") && cfg!(target_arch = "aarch64") {
-        os = TargetOs::MacOs;
-        arch = TargetArch::Arm64;
-        ext_str = ""
-    } else if cfg!(target_os = "linux") && cfg!(target_arch = "x86_64") {
-        os = TargetOs::Linux;
-        arch = TargetArch::Amd64;
-        ext_str = ""
-    } else {
-        panic!("architecture not supported")
-    };
-
-    let mut necessary_downloads = vec![];
-
-    let expected_versions = get_expected_versions()?;
-    let actual_versions = get_actual_versions()?;
-
-    if expected_versions.docker != actual_versions.docker {
-        let docker_version = expected_versions
-            .docker
-            .ok_or(anyhow!("failed to read config"))?;
-        let docker = Download {
-            target: format!("docker{}", ext_str),
-            source: get_docker_url(&os, &arch, docker_version),
-        };
-        necessary_downloads.push(docker);
+/// Returns the downloads for all tools whose configured version is not installed yet.
+pub async fn get_necessary_downloads(
+    platform: Platform,
+    lock: Option<&Lockfile>,
+) -> Result<Vec<Download>> {
+    let mut downloads = vec![];
+
+    for download in get_all_downloads(platform, lock).await? {
+        if !download
+            .tool
+            .version_path(&download.version, &platform)?
+            .exists()
+        {
+            downloads.push(download);
+        }
     }
 
-    if expected_versions.buildx != actual_versions.buildx {
-        let buildx_version = expected_versions
-            .buildx
-            .ok_or(anyhow!("failed to read config"))?;
-        let buildx = Download {
-            target: format!("docker-buildx{}", ext_str),
-            source: get_buildx_url(&os, &arch, buildx_version),
-        };
-        necessary_downloads.push(buildx);
-    }
+    Ok(downloads)
+}
 
-    if expected_versions.kubectl != actual_versions.kubectl {
-        let kubectl_version = expected_versions
-            .kubectl
-            .ok_or(anyhow!("failed to read config"))?;
-        let kubectl = Download {
-            target: format!("kubectl{}", ext_str),
-            source: get_kubectl_url(&os, &arch, kubectl_version),
-        };
-        necessary_downloads.push(kubectl);
+/// Returns the downloads for all tools with a configured version. Tools locked at a version
+/// their configured one accepts are downloaded from the locked url and checked against the locked
+/// digest.
+pub async fn get_all_downloads(
+    platform: Platform,
+    lock: Option<&Lockfile>,
+) -> Result<Vec<Download>> {
+    let config = get_config()?;
+    let mut downloads = vec![];
+
+    for (tool, version) in resolve_configured_tools(&config, lock).await? {
+        downloads.push(get_download(tool, version, platform, &config, lock)?);
     }
 
-    if expected_versions.helm != actual_versions.helm {
-        let helm_version = expected_versions
-            .helm
-            .ok_or(anyhow!("failed to read config"))?;
-        let helm = Download {
-            target: format!("helm{}", ext_str),
-            source: get_helm_url(&os, &arch, helm_version),
-        };
-        necessary_downloads.push(helm);
-    }
+    Ok(downloads)
+}
 
-    if expected_versions.mkcert != actual_versions.mkcert {
-        let mkcert_version = expected_versions
-            .mkcert
-            .ok_or(anyhow!("failed to read config"))?;
-        let mkcert = Download {
-            target: format!("mkcert{}", ext_str),
-            source: get_mkcert_url(&os, &arch, mkcert_version),
-        };
-        necessary_downloads.push(mkcert);
+/// Returns the downloads of all configured tools for every platform they are available for.
+pub async fn get_downloads_for_all_platforms() -> Result<Vec<Download>> {
+    let config = get_config()?;
+    let resolved = resolve_configured_tools(&config, None).await?;
+    let mut downloads = vec![];
+
+    for platform in Platform::all() {
+        for (tool, version) in &resolved {
+            if !tool.unsupported.contains(&platform) {
+                downloads.push(get_download(
+                    tool.clone(),
+                    version.clone(),
+                    platform,
+                    &config,
+                    None,
+                )?);
+            }
+        }
     }
 
-    if expected_versions.skaffold != actual_versions.skaffold {
-        let skaffold_version = expected_versions
-            .skaffold
-            .ok_or(anyhow!("failed to read config"))?;
-        let skaffold = Download {
-            target: format!("skaffold{}", ext_str),
-            source: get_skaffold_url(&os, &arch, skaffold_version),
-        };
-        necessary_downloads.push(skaffold);
-    }
+    Ok(downloads)
+}
 
-    if expected_versions.k9s != actual_versions.k9s {
-        let k9s_version = expected_versions
-            .k9s
-            .ok_or(anyhow!("failed to read config"))?;
-        let k9s = Download {
-            target: format!("k9s{}", ext_str),
-            source: get_k9s_url(&os, &arch, k9s_version),
+/// Returns every configured tool with the exact version to install. A locked version is kept as
+/// long as the configured version accepts it, ranges and `latest` are resolved otherwise.
+pub async fn resolve_configured_tools(
+    config: &KrunchConfig,
+    lock: Option<&Lockfile>,
+) -> Result<Vec<(Tool, String)>> {
+    let client = get_http_client(&config.download)?;
+    let mut resolved = vec![];
+
+    for (tool, spec) in get_configured_tools(config)? {
+        let locked = lock
+            .and_then(|lock| lock.tools.get(&tool.name))
+            .map(|locked| &locked.version)
+            .filter(|version| VersionSpec::parse(&spec).matches(version));
+
+        let version = match locked {
+            Some(version) => version.clone(),
+            None => resolve_release(&tool, &spec, &client, config).await?,
         };
-        necessary_downloads.push(k9s);
+        resolved.push((tool, version));
     }
 
-    Ok(necessary_downloads)
-}
-
-fn get_docker_url(os: &TargetOs, arch: &TargetArch, version: String) -> Url {
-    let os_str = match os {
-        TargetOs::Windows => "win",
-        TargetOs::MacOs => "mac",
-        TargetOs::Linux => "linux",
-    };
-
-    let ext = match os {
-        TargetOs::Windows => ".zip",
-        TargetOs::MacOs => ".tgz",
-        TargetOs::Linux => ".tgz",
-    };
-
-    let arch_str = match arch {
-        TargetArch::Amd64 => "x86_64",
-        TargetArch::Arm64 => "aarch64",
-    };
-
-    return Url::parse(&*format!(
-        "https://download.docker.com/{}/static/stable/{}/docker-{}{}",
-        os_str, arch_str, version, ext
-    ))
-    .expect("failed to parse URL");
-}
-
-fn get_kubectl_url(os: &TargetOs, arch: &TargetArch, version: String) -> Url {
-    let os_str = match os {
-        TargetOs::Windows => "windows",
-        TargetOs::MacOs => "darwin",
-        TargetOs::Linux => "linux",
-    };
-
-    let ext = match os {
-        TargetOs::Windows => ".exe",
-        TargetOs::MacOs => "",
-        TargetOs::Linux => "",
-    };
-
-    let arch_str = match arch {
-        TargetArch::Amd64 => "amd64",
-        TargetArch::Arm64 => "arm64",
-    };
-
-    return Url::parse(&*format!(
-        "https://dl.k8s.io/v{}/bin/{}/{}/kubectl{}",
-        version, os_str, arch_str, ext
-    ))
-    .expect("failed to parse URL");
+    Ok(resolved)
 }
 
-fn get_helm_url(os: &TargetOs, arch: &TargetArch, version: String) -> Url {
-    let os_str = match os {
-        TargetOs::Windows => "windows",
-        TargetOs::MacOs => "darwin",
-        TargetOs::Linux => "linux",
-    };
-
-    let ext = match os {
-        TargetOs::Windows => ".zip",
-        TargetOs::MacOs => ".tar.gz",
-        TargetOs::Linux => ".tar.gz",
-    };
-
-    let arch_str = match arch {
-        TargetArch::Amd64 => "amd64",
-        TargetArch::Arm64 => "arm64",
-    };
-
-    return Url::parse(&*format!(
-        "https://get.helm.sh/helm-v{}-{}-{}{}",
-        version, os_str, arch_str, ext
-    ))
-    .expect("failed to parse URL");
-}
-
-fn get_mkcert_url(os: &TargetOs, arch: &TargetArch, version: String) -> Url {
-    let os_str = match os {
-        TargetOs::Windows => "windows",
-        TargetOs::MacOs => "darwin",
-        TargetOs::Linux => "linux",
-    };
-
-    let arch_str = match arch {
-        TargetArch::Amd64 => "amd64",
-        TargetArch::Arm64 => "arm64",
-    };
-
-    return Url::parse(&*format!(
-        "https://dl.filippo.io/mkcert/v{}?for={}/{}",
-        version, os_str, arch_str
-    ))
-    .expect("failed to parse URL");
-}
-
-fn get_skaffold_url(os: &TargetOs, arch: &TargetArch, version: String) -> Url {
-    let os_str = match os {
-        TargetOs::Windows => "windows",
-        TargetOs::MacOs => "darwin",
-        TargetOs::Linux => "linux",
-    };
-
-    let ext = match os {
-        TargetOs::Windows => ".exe",
-        TargetOs::MacOs => "",
-        TargetOs::Linux => "",
-    };
-
-    let arch_str = match arch {
-        TargetArch::Amd64 => "amd64",
-        TargetArch::Arm64 => "arm64",
-    };
-
-    return Url::parse(&*format!(
-        "https://storage.googleapis.com/skaffold/releases/v{}/skaffold-{}-{}{}",
-        version, os_str, arch_str, ext
-    ))
-    .expect("failed to parse URL");
-}
-
-fn get_k9s_url(os: &TargetOs, arch: &TargetArch, version: String) -> Url {
-    let os_str = match os {
-        TargetOs::Windows => "Windows",
-        TargetOs::MacOs => "Darwin",
-        TargetOs::Linux => "Linux",
-    };
-
-    let ext = match os {
-        TargetOs::Windows => ".tar.gz",
-        TargetOs::MacOs => ".tar.gz",
-        TargetOs::Linux => ".tar.gz",
-    };
-
-    let arch_str = match arch {
-        TargetArch::Amd64 => "amd64",
-        TargetArch::Arm64 => "arm64",
-    };
+/// Returns every tool with a configured version together with that version, which may be a
+/// range.
+pub fn get_configured_tools(config: &KrunchConfig) -> Result<Vec<(Tool, String)>> {
+    let tools = get_tools()?;
+
+    if let Some(unknown) = config
+        .versions
+        .keys()
+        .find(|name| !tools.contains_key(*name))
+    {
+        return Err(anyhow!(
+            "no definition found for tool '{}', add it to the tools in {}",
+            unknown,
+            get_config_file_path()?.display()
+        ));
+    }
 
-    return Url::parse(&*format!(
-        "https://github.com/derailed/k9s/releases/download/v{}/k9s_{}_{}{}",
-        version, os_str, arch_str, ext
-    ))
-    .expect("failed to parse URL");
+    Ok(tools
+        .into_iter()
+        .filter_map(|(name, tool)| {
+            let version = config.versions.get(&name)?.clone();
+            Some((tool, version))
+        })
+        .collect())
 }
 
-fn get_buildx_url(os: &TargetOs, arch: &TargetArch, version: String) -> Url {
-    let os_str = match os {
-        TargetOs::Windows => "windows",
-        TargetOs::MacOs => "darwin",
-        TargetOs::Linux => "linux",
-    };
-
-    let ext = match os {
-        TargetOs::Windows => ".exe",
-        TargetOs::MacOs => "",
-        TargetOs::Linux => "",
-    };
-
-    let arch_str = match arch {
-        TargetArch::Amd64 => "amd64",
-        TargetArch::Arm64 => "arm64",
-    };
-
-    return Url::parse(&*format!(
-        "https://github.com/docker/buildx/releases/download/v{}/buildx-v{}.{}-{}{}",
-        version, version, os_str, arch_str, ext
-    ))
-    .expect("failed to parse URL");
+fn get_download(
+    tool: Tool,
+    version: String,
+    platform: Platform,
+    config: &KrunchConfig,
+    lock: Option<&Lockfile>,
+) -> Result<Download> {
+    let locked = lock.and_then(|lock| lock.artifact(&tool.name, &version, &platform));
+
+    let source = match locked {
+        Some(locked) => Url::parse(&locked.url)?,
+        None => tool.url(&platform, &version)?,
+    };
+    let sha256 = match locked {
+        Some(locked) => Some(locked.sha256.clone()),
+        None => match config.checksums.get(source.as_str()) {
+            Some(sha256) => Some(sha256.clone()),
+            None => get_recorded_digest(source.as_str())?,
+        },
+    };
+
+    Ok(Download {
+        checksum: tool.checksum_url(&platform, &version)?,
+        sha256,
+        source,
+        tool,
+        version,
+        platform,
+    })
 }
@@ -1,7 +1,11 @@
+use crate::cli_remove::remove_ingress::remove_ingress;
 use crate::get_minikube_client;
-use crate::shared::file_folder_paths::{get_binary_path, Binary};
+use crate::shared::file_folder_paths::get_binary_path;
 use crate::shared::handle_output;
+use crate::shared::state::{record_change, Change};
+use crate::shared::steps::{Check, Outcome, Step, StepOptions};
 use anyhow::Result;
+use async_trait::async_trait;
 use k8s_openapi::api::core::v1::Node;
 use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
 use kube::api::{Patch, PatchParams};
@@ -10,74 +14,147 @@ use serde_json::Value;
 use std::collections::BTreeMap;
 use std::process::Command;
 
-pub async fn enable_ingress_addon_if_needed() -> Result<()> {
+const PRIMARY_LABEL: &str = "minikube.k8s.io/primary";
+
+/// Enables minikube's ingress addon.
+pub struct IngressStep;
+
+#[async_trait(?Send)]
+impl Step for IngressStep {
+    fn name(&self) -> &'static str {
+        "ingress"
+    }
+
+    fn describe(&self) -> &'static str {
+        "enabling ingress addon"
+    }
+
+    fn describe_revert(&self) -> Option<&'static str> {
+        Some("disabling ingress addon")
+    }
+
+    async fn check(&self) -> Vec<Check> {
+        let label = "ingress addon";
+        match is_ingress_addon_enabled() {
+            Ok(true) => vec![Check::ok(label, "enabled")],
+            Ok(false) => vec![Check::problem(
+                label,
+                "disabled",
+                "enable it with `minikube addons enable ingress`",
+                true,
+            )],
+            Err(err) => vec![Check::unavailable(label, err)],
+        }
+    }
+
+    async fn apply(&self, options: &StepOptions) -> Result<Outcome> {
+        enable_ingress_addon_if_needed(options.dry_run).await
+    }
+
+    async fn revert(&self, options: &StepOptions) -> Result<Outcome> {
+        remove_ingress(self.name(), options.dry_run).await
+    }
+}
+
+pub async fn enable_ingress_addon_if_needed(dry_run: bool) -> Result<Outcome> {
+    if dry_run {
+        let mut actions = vec![];
+        if !get_node_labels().await?.contains_key(PRIMARY_LABEL) {
+            actions.push(format!("label node minikube with {}=true", PRIMARY_LABEL));
+        }
+        if !is_ingress_addon_enabled()? {
+            actions.push("run `minikube addons enable ingress`".to_string());
+        }
+
+        if actions.is_empty() {
+            return Ok(Outcome::AlreadyDone);
+        }
+        return Ok(Outcome::Message(format!("would {}", actions.join(" and "))));
+    }
+
     add_node_primary_label_if_not_exists().await?;
-    let status: Value = get_minikbe_addons()?;
 
-    if status["ingress"]["Status"] == "enabled" {
-        println!("already done")
+    if is_ingress_addon_enabled()? {
+        Ok(Outcome::AlreadyDone)
     } else {
         enable_minikube_ingress_addon()?;
-        println!("success")
+        record_change(
+            "ingress",
+            Change::Addon {
+                name: "ingress".to_string(),
+            },
+        )?;
+        Ok(Outcome::Done)
     }
-
-    Ok(())
 }
 
-async fn add_node_primary_label_if_not_exists() -> Result<()> {
+async fn get_node_labels() -> Result<BTreeMap<String, String>> {
     let client = get_minikube_client().await?;
 
     let nodes: Api<Node> = Api::all(client);
     let node = nodes.get("minikube").await?;
 
-    match node.metadata.labels {
-        Some(labels) if labels.contains_key("minikube.k8s.io/primary") => {}
-        _ => {
-            let mut new_labels = match node.metadata.labels {
-                Some(labels) => labels,
-                None => BTreeMap::new(),
-            };
-
-            new_labels.insert("minikube.k8s.io/primary".to_string(), "true".to_string());
-
-            let patch = Patch::Apply(Node {
-                metadata: ObjectMeta {
-                    labels: Some(new_labels),
-                    ..Default::default()
-                },
-                ..Default::default()
-            });
+    Ok(node.metadata.labels.unwrap_or_default())
+}
 
-            nodes
-                .patch("minikube", &PatchParams::apply("krunch"), &patch)
-                .await?;
-        }
+async fn add_node_primary_label_if_not_exists() -> Result<()> {
+    let mut labels = get_node_labels().await?;
+
+    if !labels.contains_key(PRIMARY_LABEL) {
+        labels.insert(PRIMARY_LABEL.to_string(), "true".to_string());
+
+        let patch = Patch::Apply(Node {
+            metadata: ObjectMeta {
+                labels: Some(labels),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+
+        let nodes: Api<Node> = Api::all(get_minikube_client().await?);
+        nodes
+            .patch("minikube", &PatchParams::apply("krunch"), &patch)
+            .await?;
+        record_change(
+            "ingress",
+            Change::NodeLabel {
+                node: "minikube".to_string(),
+                key: PRIMARY_LABEL.to_string(),
+            },
+        )?;
     }
 
     Ok(())
 }
 
+pub fn is_ingress_addon_enabled() -> Result<bool> {
+    is_addon_enabled("ingress")
+}
+
+pub fn is_addon_enabled(name: &str) -> Result<bool> {
+    let status: Value = get_minikube_addons()?;
+    Ok(status[name]["Status"] == "enabled")
+}
+
 fn enable_minikube_ingress_addon() -> Result<()> {
-    let output = Command::new(get_binary_path(Binary::Minikube)?)
+    let output = Command::new(get_binary_path("minikube")?)
         .arg("addons")
         .arg("enable")
         .arg("ingress")
-        .output()
-        .expect("failed to execute process");
+        .output()?;
 
     handle_output(output)?;
 
     Ok(())
 }
 
-fn get_minikbe_addons() -> Result<Value> {
-    let output = Command::new(get_binary_path(Binary::Minikube)?)
+fn get_minikube_addons() -> Result<Value> {
+    let output = Command::new(get_binary_path("minikube")?)
         .arg("addons")
         .arg("list")
         .arg("--output")
         .arg("json")
-        .output()
-        .expect("failed to execute process");
+        .output()?;
 
     let value: Value = serde_json::from_str(&handle_output(output)?)?;
 
@@ -1,11 +1,12 @@
-use crate::shared::file_folder_paths::{get_binary_path, get_config_file_path, Binary};
-use crate::shared::handle_output;
+use crate::shared::file_folder_paths::get_config_file_path;
+use crate::shared::releases::VersionSpec;
 use anyhow::{anyhow, Result};
-use serde::Deserialize;
-use serde_json::json;
-use std::fs::{create_dir_all, File};
-use std::io::{Read, Write};
-use std::process::Command;
+use serde_json::{json, Map, Value};
+use std::fs;
+use std::fs::create_dir_all;
+use std::io::Write;
+use std::path::Path;
+use tempfile::NamedTempFile;
 
 const KUBECTL_VERSION: &str = "1.28.3";
 const HELM_VERSION: &str = "3.14.4";
@@ -15,127 +16,137 @@ const K9S_VERSION: &str = "0.27.3";
 const DOCKER_VERSION: &str = "23.0.4";
 const BUILDX_VERSION: &str = "0.10.4";
 
-#[derive(Debug, Deserialize)]
-pub struct Versions {
-    pub kubectl: Option<String>,
-    pub helm: Option<String>,
-    pub mkcert: Option<String>,
-    pub skaffold: Option<String>,
-    pub k9s: Option<String>,
-    pub docker: Option<String>,
-    pub buildx: Option<String>,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct KrunchConfig {
-    versions: Versions,
-}
-
-pub fn get_expected_versions() -> Result<Versions> {
-    let mut file = File::open(get_config_file_path()?)?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
-
-    let config: KrunchConfig = serde_json::from_str(&contents)?;
-
-    Ok(config.versions)
-}
-
-pub fn get_actual_versions() -> Result<Versions> {
-    Ok(Versions {
-        kubectl: get_kubectl_version()?,
-        helm: get_helm_version()?,
-        mkcert: get_mkcert_version()?,
-        skaffold: get_skaffold_version()?,
-        k9s: get_k9s_version()?,
-        docker: get_docker_version()?,
-        buildx: get_buildx_version()?,
-    })
+/// The tool versions a new config file starts with.
+pub fn get_default_versions() -> Vec<(&'static str, &'static str)> {
+    vec![
+        ("kubectl", KUBECTL_VERSION),
+        ("helm", HELM_VERSION),
+        ("mkcert", MKCERT_VERSION),
+        ("skaffold", SKAFFOLD_VERSION),
+        ("k9s", K9S_VERSION),
+        ("docker", DOCKER_VERSION),
+        ("buildx", BUILDX_VERSION),
+    ]
 }
 
 pub fn create_default_config_if_needed() -> Result<()> {
     if !get_config_file_path()?.exists() {
-        let versions = json!({
-            "versions": {
-                "kubectl": KUBECTL_VERSION,
-                "helm": HELM_VERSION,
-                "mkcert": MKCERT_VERSION,
-                "skaffold": SKAFFOLD_VERSION,
-                "k9s": K9S_VERSION,
-                "docker": DOCKER_VERSION,
-                "buildx": BUILDX_VERSION
-            }
-        });
-
-        create_dir_all(
-            get_config_file_path()?
-                .parent()
-                .ok_or(anyhow!("failed to create config file path"))?,
-        )?;
-        let mut file = File::create(get_config_file_path()?)?;
-        file.write_all(serde_json::to_string_pretty(&versions)?.as_bytes())?;
+        let versions: Map<String, Value> = get_default_versions()
+            .into_iter()
+            .map(|(name, version)| (name.to_string(), json!(version)))
+            .collect();
+        let versions = json!({ "versions": versions });
+
+        write_config(&get_config_file_path()?, &versions)?;
     }
 
     Ok(())
 }
 
-fn get_kubectl_version() -> Result<Option<String>> {
-    get_any_version(
-        Binary::Kubectl,
-        "version",
-        r#"^Client Version.*?v([\d\.]+)"#,
-    )
-}
-
-fn get_docker_version() -> Result<Option<String>> {
-    get_any_version(Binary::Docker, "version", r"Client:\s+Version:\s+([^\s]+)")
-}
-
-fn get_buildx_version() -> Result<Option<String>> {
-    get_any_version(Binary::Buildx, "version", r"v(\d+\.\d+\.\d+)")
-}
+/// Sets the configured version of each tool in `versions`, e.g. those installed from a bundle,
+/// so that the shims run them. A configured range or `latest` that already accepts the version
+/// is kept, and a missing config is created with just these versions. Returns the names of the
+/// tools whose version changes, with `dry_run` without writing anything.
+pub fn pin_versions(
+    path: &Path,
+    versions: &[(String, String)],
+    dry_run: bool,
+) -> Result<Vec<String>> {
+    let mut config = if path.exists() {
+        serde_json::from_str(&fs::read_to_string(path)?)
+            .map_err(|err| anyhow!("failed to parse {}: {}", path.display(), err))?
+    } else {
+        json!({})
+    };
+    let configured = config
+        .as_object_mut()
+        .ok_or(anyhow!("{} is not a JSON object", path.display()))?
+        .entry("versions")
+        .or_insert_with(|| json!({}))
+        .as_object_mut()
+        .ok_or(anyhow!("`versions` in {} is not an object", path.display()))?;
+
+    let mut changed = vec![];
+    for (name, version) in versions {
+        let accepted = configured
+            .get(name)
+            .and_then(Value::as_str)
+            .is_some_and(|spec| VersionSpec::parse(spec).matches(version));
+        if !accepted {
+            configured.insert(name.clone(), json!(version));
+            changed.push(name.clone());
+        }
+    }
 
-fn get_helm_version() -> Result<Option<String>> {
-    get_any_version(Binary::Helm, "version", r#"Version:"v([\d\.]+)""#)
-}
+    if !dry_run && (!changed.is_empty() || !path.exists()) {
+        write_config(path, &config)?;
+    }
 
-fn get_skaffold_version() -> Result<Option<String>> {
-    get_any_version(Binary::Skaffold, "version", r"v(\d+\.\d+\.\d+)")
+    Ok(changed)
 }
 
-fn get_k9s_version() -> Result<Option<String>> {
-    get_any_version(Binary::K9S, "version", r"v(\d+\.\d+\.\d+)")
-}
+/// Writes next to the config and renames into place, so that an interrupted write can't leave a
+/// truncated config behind.
+fn write_config(path: &Path, config: &Value) -> Result<()> {
+    let folder = path
+        .parent()
+        .ok_or(anyhow!("failed to create config file path"))?;
+    create_dir_all(folder)?;
+    let mut tmp_file = NamedTempFile::new_in(folder)?;
+    tmp_file.write_all(serde_json::to_string_pretty(config)?.as_bytes())?;
+    tmp_file.persist(path)?;
 
-fn get_mkcert_version() -> Result<Option<String>> {
-    get_any_version(Binary::Mkcert, "-version", r"v(\d+\.\d+\.\d+)")
+    Ok(())
 }
 
-fn get_any_version(binary: Binary, command: &str, regex: &str) -> Result<Option<String>> {
-    let output = match Command::new(get_binary_path(binary)?).arg(command).output() {
-        Ok(inner) => inner,
-        Err(_) => return Ok(None),
-    };
-
-    let raw_version_string: String = match handle_output(output) {
-        Ok(inner) => inner,
-        Err(_) => return Ok(None),
-    };
-
-    let re = regex::Regex::new(regex)?;
-    let captures = re.captures(raw_version_string.as_str());
-
-    let parsed_output = captures
-        .ok_or(anyhow!(
-            "not able to detect version: {}",
-            raw_version_string
-        ))?
-        .get(1)
-        .ok_or(anyhow!(
-            "not able to detect version: {}",
-            raw_version_string
-        ))?
-        .as_str()
-        .to_string();
-    Ok(Some(parsed_output))
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pin_bundle_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        let defaults: Map<String, Value> = get_default_versions()
+            .into_iter()
+            .map(|(name, version)| (name.to_string(), json!(version)))
+            .collect();
+        let mut config = json!({ "versions": defaults, "parallel_downloads": 2 });
+        config["versions"]["helm"] = json!("~3.13");
+        write_config(&path, &config).unwrap();
+
+        // versions pinned on the machine the bundle was created on
+        let bundled = [
+            ("kubectl".to_string(), "1.27.9".to_string()),
+            ("helm".to_string(), "3.13.3".to_string()),
+            ("stern".to_string(), "1.28.0".to_string()),
+        ];
+        assert_ne!(bundled[0].1, KUBECTL_VERSION);
+
+        assert_eq!(
+            pin_versions(&path, &bundled, true).unwrap(),
+            ["kubectl", "stern"]
+        );
+        assert_eq!(
+            serde_json::from_str::<Value>(&fs::read_to_string(&path).unwrap()).unwrap(),
+            config
+        );
+
+        assert_eq!(
+            pin_versions(&path, &bundled, false).unwrap(),
+            ["kubectl", "stern"]
+        );
+        let pinned: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(pinned["versions"]["kubectl"], "1.27.9");
+        assert_eq!(pinned["versions"]["stern"], "1.28.0");
+        // the range accepts the bundled version, other settings and tools stay as they were
+        assert_eq!(pinned["versions"]["helm"], "~3.13");
+        assert_eq!(pinned["versions"]["k9s"], K9S_VERSION);
+        assert_eq!(pinned["parallel_downloads"], 2);
+
+        let missing = dir.path().join("new/config.json");
+        pin_versions(&missing, &bundled, false).unwrap();
+        let created: Value = serde_json::from_str(&fs::read_to_string(&missing).unwrap()).unwrap();
+        assert_eq!(created["versions"].as_object().unwrap().len(), 3);
+    }
 }
@@ -1,55 +1,37 @@
-use crate::cli_install::bin_folder_to_path::add_bin_folder_to_path;
-use crate::cli_install::create_ca_and_tls::create_ca_and_tls;
-use crate::cli_install::dns_for_minikube::add_dns_for_minikube;
-use crate::cli_install::docker_to_minikube::point_docker_to_minikube;
-use crate::cli_install::download_binaries::download_all;
-use crate::cli_install::enable_ingress::enable_ingress_addon_if_needed;
-use crate::shared::should_continue_as_admin;
+use crate::shared::steps::{apply_steps, StepOptions};
+use crate::shared::tools::Platform;
+use crate::shared::AdminMode;
 use anyhow::Result;
-use std::io;
-use std::io::Write;
-
-mod bin_folder_to_path;
-mod create_ca_and_tls;
-mod dns_for_minikube;
-mod docker_to_minikube;
-mod download_binaries;
-mod download_urls;
-mod enable_ingress;
+use std::path::Path;
+
+pub mod bin_folder_to_path;
+pub mod checksums;
+pub mod create_ca_and_tls;
+pub mod dns_for_minikube;
+pub mod docker_to_minikube;
+pub mod download_binaries;
+pub mod download_urls;
+pub mod enable_ingress;
 mod get_versions;
 
-pub async fn cli_install() -> Result<()> {
-    print!("{:<35}", "downloading tools");
-    io::stdout().flush().unwrap();
-    download_all().await?;
-
-    print!("{:<35}", "adding tools to PATH");
-    io::stdout().flush().unwrap();
-    add_bin_folder_to_path().await?;
-
-    print!("{:<35}", "point docker cli to minikube");
-    io::stdout().flush().unwrap();
-    point_docker_to_minikube().await?;
-
-    print!("{:<35}", "enabling ingress addon");
-    io::stdout().flush().unwrap();
-    enable_ingress_addon_if_needed().await?;
-
-    if should_continue_as_admin()? {
-        print!("{:<35}", "creating DNS entry");
-        io::stdout().flush().unwrap();
-        add_dns_for_minikube()?;
-
-        print!("{:<35}", "creating CA and TLS secret");
-        io::stdout().flush().unwrap();
-        create_ca_and_tls().await?;
-    } else {
-        println!("{:<35}{}", "creating DNS entry", "skipped (not admin)");
-        println!(
-            "{:<35}{}",
-            "creating CA and TLS secret", "skipped (not admin)"
-        );
-    }
-
-    Ok(())
+/// Runs the selected install steps in order, or with `dry_run` only prints what would change
+/// without touching anything. When a step fails, the steps applied before it are reverted unless
+/// `rollback` is off.
+pub async fn cli_install(
+    platform: Option<Platform>,
+    bundle: Option<&Path>,
+    locked: bool,
+    dry_run: bool,
+    admin: AdminMode,
+    steps: &[String],
+    rollback: bool,
+) -> Result<()> {
+    let options = StepOptions {
+        dry_run,
+        platform,
+        bundle,
+        locked,
+    };
+
+    apply_steps(steps, &options, admin, rollback).await
 }
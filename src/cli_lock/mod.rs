@@ -0,0 +1,110 @@
+use crate::cli_install::checksums::get_expected_digest;
+use crate::cli_install::download_binaries::fetch_all;
+use crate::cli_install::download_urls::{get_downloads_for_all_platforms, Download};
+use crate::shared::config::{get_config, KrunchConfig};
+use crate::shared::file_folder_paths::get_lock_file_path;
+use crate::shared::http::{fetch_size, get_http_client};
+use crate::shared::lock::{LockedArtifact, LockedTool, Lockfile};
+use crate::shared::sha256_of_file;
+use anyhow::{anyhow, Result};
+use futures::stream;
+use futures_util::StreamExt;
+use reqwest::Client;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+/// Locks tools with a pinned or published checksum from that checksum and a HEAD request, only
+/// the others are downloaded to hash them.
+pub async fn cli_lock() -> Result<()> {
+    let downloads = get_downloads_for_all_platforms().await?;
+    if downloads.is_empty() {
+        return Err(anyhow!("no tool versions configured, nothing to lock"));
+    }
+
+    // locking is how digests of tools without a published checksum get recorded, and those
+    // downloads are only hashed, so they stay out of the install cache
+    let mut config = get_config()?;
+    config.require_checksums = false;
+    config.record_checksums = false;
+    config.cache.max_size_mb = 0;
+    let client = get_http_client(&config.download)?;
+
+    let published: Vec<Result<Option<LockedArtifact>>> = stream::iter(&downloads)
+        .map(|download| lock_from_checksum(download, &client, &config))
+        .buffered(config.parallel_downloads.max(1))
+        .collect()
+        .await;
+
+    let lock = RefCell::new(Lockfile::default());
+    let add = |download: &Download, artifact: LockedArtifact| {
+        lock.borrow_mut()
+            .tools
+            .entry(download.tool.name.clone())
+            .or_insert_with(|| LockedTool {
+                version: download.version.clone(),
+                platforms: BTreeMap::new(),
+            })
+            .platforms
+            .insert(download.platform.to_string(), artifact);
+    };
+
+    let mut failed = vec![];
+    let mut unpublished = vec![];
+    for (download, result) in downloads.into_iter().zip(published) {
+        let target = format!("{} ({})", download.target(), download.platform);
+        match result {
+            Ok(Some(artifact)) => {
+                add(&download, artifact);
+                println!("{:<34} success", format!("locking {}", target));
+            }
+            Ok(None) => unpublished.push(download),
+            Err(err) => {
+                println!("{:<34} failed: {}", format!("locking {}", target), err);
+                failed.push(target);
+            }
+        }
+    }
+    if !failed.is_empty() {
+        return Err(anyhow!("failed to lock {}", failed.join(", ")));
+    }
+
+    if !unpublished.is_empty() {
+        fetch_all(&config, &unpublished, |download, artifact| {
+            let artifact = LockedArtifact {
+                url: download.source.to_string(),
+                sha256: sha256_of_file(artifact)?,
+                size: Some(artifact.metadata()?.len()),
+            };
+            add(download, artifact);
+            Ok(())
+        })
+        .await?;
+    }
+
+    print!(
+        "{:<34} ",
+        format!("writing {}", get_lock_file_path()?.display())
+    );
+    lock.into_inner().write()?;
+    println!("success");
+
+    Ok(())
+}
+
+/// The locked artifact from a pinned or published checksum, `None` if there is neither.
+async fn lock_from_checksum(
+    download: &Download,
+    client: &Client,
+    config: &KrunchConfig,
+) -> Result<Option<LockedArtifact>> {
+    let sha256 = match get_expected_digest(download, client, config).await? {
+        Some(sha256) => sha256,
+        None => return Ok(None),
+    };
+
+    Ok(Some(LockedArtifact {
+        url: download.source.to_string(),
+        sha256,
+        size: fetch_size(client, &config.download, &download.source).await?,
+    }))
+}
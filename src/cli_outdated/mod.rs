@@ -0,0 +1,65 @@
+use crate::cli_install::download_urls::get_configured_tools;
+use crate::shared::config::get_config;
+use crate::shared::http::get_http_client;
+use crate::shared::releases::{get_releases, release_order, VersionSpec};
+use crate::shared::shims::resolve_version;
+use anyhow::Result;
+
+/// Lists the installed tools for which a newer release exists, within their configured range
+/// (`wanted`) or beyond it (`latest`).
+pub async fn cli_outdated() -> Result<()> {
+    let config = get_config()?;
+    let client = get_http_client(&config.download)?;
+    let mut rows = vec![];
+    let mut errors = vec![];
+
+    for (tool, spec) in get_configured_tools(&config)? {
+        let installed = match resolve_version(&tool) {
+            Ok(version) if tool.installed_versions()?.contains(&version) => version,
+            _ => continue,
+        };
+
+        let releases = match get_releases(&tool, &client, &config).await {
+            Ok(releases) => releases,
+            Err(err) => {
+                errors.push(format!("{:<12}failed: {}", tool.name, err));
+                continue;
+            }
+        };
+        let releases = releases.iter().map(String::as_str);
+        let wanted = VersionSpec::parse(&spec)
+            .select(releases.clone())
+            .unwrap_or(installed.clone());
+        let latest = VersionSpec::Latest
+            .select(releases)
+            .unwrap_or(installed.clone());
+
+        // an installed version above the range, e.g. pinned by a project, isn't outdated
+        if release_order(&installed) < release_order(&wanted)
+            || release_order(&installed) < release_order(&latest)
+        {
+            rows.push((tool.name, spec, installed, wanted, latest));
+        }
+    }
+
+    if rows.is_empty() {
+        println!("all installed tools are up to date");
+    } else {
+        println!(
+            "{:<12}{:<14}{:<14}{:<14}latest",
+            "tool", "configured", "installed", "wanted"
+        );
+        for (name, spec, installed, wanted, latest) in rows {
+            println!(
+                "{:<12}{:<14}{:<14}{:<14}{}",
+                name, spec, installed, wanted, latest
+            );
+        }
+    }
+
+    for error in errors {
+        println!("{}", error);
+    }
+
+    Ok(())
+}
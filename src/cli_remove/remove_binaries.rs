@@ -1,36 +1,74 @@
-use crate::shared::file_folder_paths::{get_buildx_folder, get_krunch_folder};
+use crate::shared::file_folder_paths::{
+    get_backups_folder, get_cache_folder, get_krunch_folder, get_recorded_checksums_path,
+    get_state_file_path,
+};
+use crate::shared::state::State;
+use crate::shared::steps::Outcome;
+use crate::shared::tools::{get_bundled_tools, get_tools, ToolFolder};
 use anyhow::Result;
 use std::fs;
+use std::path::PathBuf;
 
-pub fn remove_binaries() -> Result<()> {
-    let mut nothing_done = true;
+pub fn remove_binaries(dry_run: bool) -> Result<Outcome> {
+    let paths = get_paths_to_remove()?;
 
-    let krunch_folder = get_krunch_folder()?;
-    if krunch_folder.exists() {
-        nothing_done = false;
-        fs::remove_dir_all(krunch_folder)?;
+    if paths.is_empty() {
+        return Ok(Outcome::NothingToDo);
     }
 
-    let extension = if cfg!(target_os = "windows") {
-        ".exe"
+    if dry_run {
+        let mut plan = "would delete".to_string();
+        for path in paths {
+            plan.push_str(&format!("\n  {}", path.display()));
+        }
+        Ok(Outcome::Message(plan))
     } else {
-        ""
-    };
+        for path in paths {
+            if path.is_dir() {
+                fs::remove_dir_all(path)?;
+            } else {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(Outcome::Done)
+    }
+}
 
-    let mut buildx_file_path = get_buildx_folder()?;
-    let build_file = format!("docker-buildx{}", extension);
-    buildx_file_path.push(build_file);
+fn get_paths_to_remove() -> Result<Vec<PathBuf>> {
+    let mut paths = vec![];
 
-    if buildx_file_path.exists() {
-        nothing_done = false;
-        fs::remove_file(buildx_file_path)?;
+    // tools outside the krunch folder, e.g. docker cli plugins, have to be removed one by one
+    let mut tools = get_bundled_tools()?;
+    tools.extend(get_tools()?);
+    for tool in tools.values() {
+        let install_path = tool.install_path()?;
+        if tool.folder != ToolFolder::Bin && install_path.exists() {
+            paths.push(install_path);
+        }
     }
 
-    if nothing_done {
-        println!("nothing to do")
-    } else {
-        println!("success")
+    // the download cache is kept, so that a reinstall doesn't download everything again, the
+    // recorded checksums, so that it is checked against them, and the backups, so that a file can
+    // still be restored after remove
+    let mut keep = vec![
+        get_cache_folder()?,
+        get_recorded_checksums_path()?,
+        get_backups_folder()?,
+    ];
+    // changes that weren't reverted, e.g. skipped without admin rights, are still krunch's to
+    // remove later
+    if !State::read()?.steps.is_empty() {
+        keep.push(get_state_file_path()?);
+    }
+    let krunch_folder = get_krunch_folder()?;
+    if krunch_folder.exists() {
+        for entry in fs::read_dir(krunch_folder)? {
+            let path = entry?.path();
+            if !keep.contains(&path) {
+                paths.push(path);
+            }
+        }
     }
 
-    Ok(())
+    Ok(paths)
 }
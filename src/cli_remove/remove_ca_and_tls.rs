@@ -1,58 +1,87 @@
-use crate::shared::file_folder_paths::{get_binary_path, Binary};
-use crate::shared::{get_minikube_client, handle_output, TLS_SECRET};
-use anyhow::{anyhow, Result};
+use crate::shared::file_folder_paths::get_binary_path;
+use crate::shared::get_minikube_client;
+use crate::shared::handle_output;
+use crate::shared::state::{Change, Reversal, State};
+use crate::shared::steps::Outcome;
+use anyhow::Result;
 use k8s_openapi::api::core::v1::Secret;
-use kube::api::DeleteParams;
-use kube::{Api, Error};
+use kube::api::{DeleteParams, Preconditions};
+use kube::Api;
 use std::process::Command;
 
-pub async fn remove_ca_and_tls_secret() -> Result<()> {
-    let mkcert_path = get_binary_path(Binary::Mkcert)?;
+/// Deletes the secret and removes the CA that install recorded for `step`. A secret of the same
+/// name that was created by someone else since is left alone.
+pub async fn remove_ca_and_tls_secret(step: &'static str, dry_run: bool) -> Result<Outcome> {
+    let mut reversal = Reversal::new(step, dry_run);
 
-    if mkcert_path.exists() {
-        remove_local_ca()?;
-        delete_tls_secret().await?;
-    } else {
-        println!("nothing to do");
+    for change in State::read()?.changes(step) {
+        let drift = match &change {
+            Change::Secret {
+                namespace,
+                name,
+                uid,
+            } => delete_secret(namespace, name, uid, dry_run).await?,
+            Change::LocalCa => remove_local_ca(dry_run)?,
+            _ => continue,
+        };
+        reversal.add(&change, drift)?;
     }
 
-    Ok(())
+    Ok(reversal.finish())
 }
 
-fn remove_local_ca() -> Result<()> {
-    let output = Command::new(get_binary_path(Binary::Mkcert)?)
-        .arg("-uninstall")
-        .output()
-        .expect("failed to execute process");
+fn remove_local_ca(dry_run: bool) -> Result<Option<String>> {
+    let mkcert_path = get_binary_path("mkcert")?;
+    if !mkcert_path.exists() {
+        return Ok(Some(
+            "mkcert is no longer installed to remove its CA".to_string(),
+        ));
+    }
+
+    if !dry_run {
+        let output = Command::new(mkcert_path).arg("-uninstall").output()?;
 
-    handle_output(output)?;
+        handle_output(output)?;
+    }
 
-    Ok(())
+    Ok(None)
 }
 
-async fn delete_tls_secret() -> Result<()> {
+async fn delete_secret(
+    namespace: &str,
+    name: &str,
+    uid: &str,
+    dry_run: bool,
+) -> Result<Option<String>> {
     let client = get_minikube_client().await?;
-    let secrets: Api<Secret> = Api::namespaced(client, "default");
-
-    let result = secrets.delete(TLS_SECRET, &DeleteParams::default()).await;
-
-    handle_resource_deletion_result(result)?;
+    let secrets: Api<Secret> = Api::namespaced(client, namespace);
 
-    Ok(())
-}
-
-fn handle_resource_deletion_result<T>(result: kube::Result<T, Error>) -> Result<()> {
-    match result {
-        Ok(_) => println!("success"),
-        Err(Error::Api(inner)) => {
-            if inner.reason == "NotFound" {
-                println!("nothing to do");
-            }
-        }
-        Err(err) => {
-            return Err(anyhow!(err));
+    let current_uid = match secrets.get_opt(name).await? {
+        Some(secret) => secret.metadata.uid.unwrap_or_default(),
+        None => {
+            return Ok(Some(format!(
+                "secret {}/{} no longer exists",
+                namespace, name
+            )))
         }
+    };
+    if current_uid != uid {
+        return Ok(Some(format!(
+            "secret {}/{} was replaced since install",
+            namespace, name
+        )));
+    }
+
+    if !dry_run {
+        let params = DeleteParams {
+            preconditions: Some(Preconditions {
+                uid: Some(uid.to_string()),
+                resource_version: None,
+            }),
+            ..Default::default()
+        };
+        secrets.delete(name, &params).await?;
     }
 
-    Ok(())
+    Ok(None)
 }
@@ -1,31 +1,54 @@
 use crate::shared::file_folder_paths::get_etc_hosts_path;
-use crate::shared::{update_etc_hosts, LINE_ENDING, MINIKUBE_HOST};
+use crate::shared::hosts_file::HostsFile;
+use crate::shared::state::{Change, Reversal, State};
+use crate::shared::steps::Outcome;
+use crate::shared::{get_diff, update_etc_hosts};
 use anyhow::Result;
 use std::fs;
 
-pub fn remove_dns_for_minikube() -> Result<()> {
-    let etc_hosts_path = get_etc_hosts_path()?;
-    let mut data = fs::read_to_string(&etc_hosts_path)?;
-    data = data.trim().to_string();
-
-    if data.contains(MINIKUBE_HOST) {
-        data = remove_dns_data(data);
-        update_etc_hosts(data)?;
+/// Deletes the hosts entry that install recorded for `step` from krunch's block, leaving any entry
+/// for k8s.local outside the block alone.
+pub fn remove_dns_for_minikube(step: &'static str, dry_run: bool) -> Result<Outcome> {
+    let mut reversal = Reversal::new(step, dry_run);
 
-        println!("success");
-    } else {
-        println!("nothing to do");
+    for change in State::read()?.changes(step) {
+        if let Change::HostsEntry { .. } = &change {
+            remove_hosts_entry(step, &change, &mut reversal, dry_run)?;
+        }
     }
 
-    Ok(())
+    Ok(reversal.finish())
 }
 
-fn remove_dns_data(mut data: String) -> String {
-    data.push_str(LINE_ENDING);
-    data.push_str(LINE_ENDING);
+/// Drops the entry of `step` from krunch's block in the hosts file, and the block with the last
+/// entry. Notes why not when the block is gone, and the diff on a dry run.
+fn remove_hosts_entry(
+    step: &str,
+    change: &Change,
+    reversal: &mut Reversal,
+    dry_run: bool,
+) -> Result<()> {
+    let etc_hosts_path = get_etc_hosts_path()?;
+    let original = fs::read_to_string(&etc_hosts_path)?;
+    let mut hosts_file = HostsFile::parse(&original);
+
+    if !hosts_file.has_block() {
+        let drift = format!(
+            "krunch's block is no longer in {}",
+            etc_hosts_path.display()
+        );
+        return reversal.add(change, Some(drift));
+    }
 
-    let re = regex::Regex::new(&format!(r"(?m)^.*k8s.local{}", LINE_ENDING)).unwrap();
-    data = re.replace(&data, "").to_string();
+    let mut state = State::read()?;
+    state.forget(step, change);
+    hosts_file.set_block(&state.hosts_lines());
+    let data = hosts_file.to_string();
+    if dry_run {
+        let diff = get_diff(&etc_hosts_path, &original, &data);
+        return reversal.add_diff(change, diff);
+    }
+    update_etc_hosts(data)?;
 
-    data.trim().to_string()
+    reversal.add(change, None)
 }
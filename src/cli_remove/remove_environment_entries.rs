@@ -1,83 +1,133 @@
-use crate::shared::file_folder_paths::{get_bin_folder, get_shell_profile_path};
+use crate::shared::managed_block::{get_block, set_profile_lines};
+use crate::shared::state::{Change, Reversal, State};
+use crate::shared::steps::Outcome;
 use crate::shared::windows_registry::{
     delete_from_environment, read_from_environment, write_to_environment,
 };
-use anyhow::{anyhow, Result};
-use std::fs::{File, OpenOptions};
-use std::io::prelude::*;
-use std::io::{BufRead, BufReader};
+use anyhow::Result;
+use std::fs;
+use std::path::Path;
 
-pub fn remove_environment_entries() -> Result<()> {
-    if cfg!(target_family = "unix") {
-        remove_environment_entries_unix()?;
-    } else if cfg!(target_family = "windows") {
-        remove_environment_entries_windows()?;
+/// Reverts the profile lines and, on Windows, the user variables that install recorded for
+/// `step`, leaving anything outside krunch's block in the profile alone.
+pub fn remove_environment_entries(step: &'static str, dry_run: bool) -> Result<Outcome> {
+    let mut reversal = Reversal::new(step, dry_run);
+
+    for change in State::read()?.changes(step) {
+        let drift = match &change {
+            Change::ProfileLines { path, .. } => {
+                remove_profile_lines(step, &change, path, &mut reversal, dry_run)?;
+                continue;
+            }
+            Change::UserVariable { name, value } => remove_user_variable(name, value, dry_run)?,
+            Change::UserPathEntry { folder } => remove_user_path_entry(folder, dry_run)?,
+            _ => continue,
+        };
+        reversal.add(&change, drift)?;
     }
 
-    Ok(())
+    Ok(reversal.finish())
 }
 
-fn remove_environment_entries_unix() -> Result<()> {
-    let path = get_shell_profile_path()?;
-
-    if !path.exists() {
-        return Err(anyhow!("File not found"));
+/// Drops the lines of `step` from krunch's block in a profile, and the block with the last of
+/// them. Notes why not when the block is gone, and the diff on a dry run.
+fn remove_profile_lines(
+    step: &str,
+    change: &Change,
+    path: &Path,
+    reversal: &mut Reversal,
+    dry_run: bool,
+) -> Result<()> {
+    let data = match fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(_) => {
+            return reversal.add(change, Some(format!("{} no longer exists", path.display())))
+        }
+    };
+    if get_block(&data).is_none() {
+        let drift = format!("krunch's block is no longer in {}", path.display());
+        return reversal.add(change, Some(drift));
     }
 
-    let file = File::open(&path)?;
-    let reader = BufReader::new(file);
-    let mut original_lines: Vec<String> = Vec::new();
-    let mut modified_lines: Vec<String> = Vec::new();
+    match set_profile_lines(step, path, vec![], |_| 0..0, dry_run)? {
+        Outcome::Message(diff) => reversal.add_diff(change, diff),
+        _ => reversal.add(change, None),
+    }
+}
 
-    for line in reader.lines() {
-        let line = line?;
-        original_lines.push(line.clone());
-        if !line.contains("# krunch")
-            && !line.contains("DOCKER_TLS_VERIFY")
-            && !line.contains("DOCKER_HOST")
-            && !line.contains("DOCKER_CERT_PATH")
-            && !line.contains("MINIKUBE_ACTIVE_DOCKERD")
-            && !line.contains("/.krunch")
-        {
-            modified_lines.push(line);
+fn remove_user_variable(name: &str, value: &str, dry_run: bool) -> Result<Option<String>> {
+    match read_from_environment(name) {
+        Ok(current) if current == value => {
+            if !dry_run {
+                delete_from_environment(name)?;
+            }
+            Ok(None)
         }
+        Ok(current) => Ok(Some(format!("{} is {} now", name, current))),
+        Err(_) => Ok(Some(format!("{} is no longer set", name))),
     }
+}
 
-    if original_lines == modified_lines {
-        println!("nothing to do");
-    } else {
-        let mut file = OpenOptions::new().write(true).truncate(true).open(&path)?;
-        for line in modified_lines {
-            writeln!(file, "{}", line)?;
-        }
-        println!("success");
+fn remove_user_path_entry(folder: &str, dry_run: bool) -> Result<Option<String>> {
+    let current_path = read_from_environment("Path")?;
+
+    let new_path = match without_path_entry(&current_path, folder) {
+        Some(new_path) => new_path,
+        None => return Ok(Some(format!("{} is no longer in Path", folder))),
+    };
+    if !dry_run {
+        write_to_environment("Path", new_path)?;
     }
 
-    Ok(())
+    Ok(None)
 }
 
-// todo: always says "success"
-fn remove_environment_entries_windows() -> Result<()> {
-    let delete_result: Result<_> = (|| {
-        delete_from_environment("DOCKER_TLS_VERIFY")?;
-        delete_from_environment("DOCKER_HOST")?;
-        delete_from_environment("DOCKER_CERT_PATH")?;
-        delete_from_environment("MINIKUBE_ACTIVE_DOCKERD")?;
+/// Removes the entry install added from a `;`-separated Path, `None` if it isn't in there. Only
+/// an entry naming exactly that folder counts, in any case as Windows paths are, and other
+/// entries, including duplicates the user added, stay as they are.
+fn without_path_entry(path: &str, folder: &str) -> Option<String> {
+    let mut entries: Vec<&str> = path.split(';').collect();
+    let index = entries
+        .iter()
+        .position(|entry| entry.eq_ignore_ascii_case(folder))?;
+    entries.remove(index);
 
-        let current_path = read_from_environment("Path")?;
-        let bin_folder = get_bin_folder()?.display().to_string().replace('/', "\\");
-        let new_path = current_path.replace(&format!(";{}", bin_folder), "");
+    Some(entries.join(";"))
+}
 
-        write_to_environment("Path", new_path)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        Ok(())
-    })();
+    #[test]
+    fn test_without_path_entry() {
+        let folder = r"C:\Users\x\.krunch\bin";
 
-    if delete_result.is_err() {
-        println!("already done");
-    } else {
-        println!("success");
+        assert_eq!(
+            without_path_entry(r"C:\Windows;C:\Users\x\.krunch\bin;C:\tools", folder).unwrap(),
+            r"C:\Windows;C:\tools"
+        );
+        // first in Path, and in another case
+        assert_eq!(
+            without_path_entry(r"c:\users\x\.KRUNCH\bin;C:\Windows", folder).unwrap(),
+            r"C:\Windows"
+        );
+        assert_eq!(
+            without_path_entry(r"C:\Windows;C:\Users\x\.krunch\bin;", folder).unwrap(),
+            r"C:\Windows;"
+        );
+        // a longer folder isn't the entry, and a duplicate stays
+        assert_eq!(
+            without_path_entry(
+                r"C:\Users\x\.krunch\bin2;C:\Users\x\.krunch\bin;C:\Users\x\.krunch\bin",
+                folder
+            )
+            .unwrap(),
+            r"C:\Users\x\.krunch\bin2;C:\Users\x\.krunch\bin"
+        );
+        assert_eq!(
+            without_path_entry(r"C:\Windows;C:\Users\x\.krunch\bin2", folder),
+            None
+        );
     }
-
-    Ok(())
 }
@@ -0,0 +1,64 @@
+use crate::cli_install::enable_ingress::is_addon_enabled;
+use crate::shared::file_folder_paths::get_binary_path;
+use crate::shared::state::{Change, Reversal, State};
+use crate::shared::steps::Outcome;
+use crate::shared::{get_minikube_client, handle_output};
+use anyhow::Result;
+use k8s_openapi::api::core::v1::Node;
+use kube::api::{Patch, PatchParams};
+use kube::Api;
+use std::process::Command;
+
+/// Disables the addons and removes the node labels that install recorded for `step`, those that
+/// were in place before stay.
+pub async fn remove_ingress(step: &'static str, dry_run: bool) -> Result<Outcome> {
+    let mut reversal = Reversal::new(step, dry_run);
+
+    for change in State::read()?.changes(step) {
+        let drift = match &change {
+            Change::NodeLabel { node, key } => remove_node_label(node, key, dry_run).await?,
+            Change::Addon { name } => disable_addon(name, dry_run)?,
+            _ => continue,
+        };
+        reversal.add(&change, drift)?;
+    }
+
+    Ok(reversal.finish())
+}
+
+async fn remove_node_label(node: &str, key: &str, dry_run: bool) -> Result<Option<String>> {
+    let nodes: Api<Node> = Api::all(get_minikube_client().await?);
+    let labels = match nodes.get_opt(node).await? {
+        Some(found) => found.metadata.labels.unwrap_or_default(),
+        None => return Ok(Some(format!("node {} no longer exists", node))),
+    };
+
+    if !labels.contains_key(key) {
+        return Ok(Some(format!("node {} no longer has label {}", node, key)));
+    }
+    if !dry_run {
+        let patch = serde_json::json!({ "metadata": { "labels": { key: null } } });
+        nodes
+            .patch(node, &PatchParams::default(), &Patch::Merge(&patch))
+            .await?;
+    }
+
+    Ok(None)
+}
+
+fn disable_addon(name: &str, dry_run: bool) -> Result<Option<String>> {
+    if !is_addon_enabled(name)? {
+        return Ok(Some(format!("the {} addon is already disabled", name)));
+    }
+    if !dry_run {
+        let output = Command::new(get_binary_path("minikube")?)
+            .arg("addons")
+            .arg("disable")
+            .arg(name)
+            .output()?;
+
+        handle_output(output)?;
+    }
+
+    Ok(None)
+}
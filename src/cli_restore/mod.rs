@@ -0,0 +1,56 @@
+use crate::shared::backups::Backups;
+use crate::shared::config::get_config;
+use crate::shared::file_folder_paths::get_etc_hosts_path;
+use crate::shared::managed_block::write_atomically;
+use crate::shared::{should_continue_as_admin, update_etc_hosts, AdminMode};
+use anyhow::{anyhow, Result};
+use std::fs;
+
+pub fn cli_restore_list() -> Result<()> {
+    let backups = Backups::open(&get_config()?.backups)?;
+    let list = backups.list()?;
+
+    if list.is_empty() {
+        println!("no backups yet");
+        return Ok(());
+    }
+
+    // ids start with the UTC time of the backup
+    println!("{:<34}path", "id");
+    for backup in list {
+        println!("{:<34}{}", backup.id, backup.path.display());
+    }
+
+    Ok(())
+}
+
+/// Puts a file back as it was when the backup was taken. The current contents are backed up
+/// first, so a restore can be undone the same way. The hosts file needs admin rights, which are
+/// asked for like during install.
+pub fn cli_restore(id: &str, admin: AdminMode) -> Result<()> {
+    let backups = Backups::open(&get_config()?.backups)?;
+    let backup = backups.get(id)?;
+    let data = backups.read(&backup)?;
+
+    if backup.path == get_etc_hosts_path()? {
+        if !should_continue_as_admin(admin)? {
+            return Err(anyhow!(
+                "restoring {} requires admin rights, nothing was changed",
+                backup.path.display()
+            ));
+        }
+        update_etc_hosts(data)?;
+    } else if backup.path.exists() {
+        write_atomically(&backup.path, &data)?;
+    } else {
+        fs::write(&backup.path, data)?;
+    }
+
+    println!(
+        "restored {} from backup {}",
+        backup.path.display(),
+        backup.id
+    );
+
+    Ok(())
+}
@@ -0,0 +1,20 @@
+use crate::shared::steps::{get_steps, CheckStatus};
+use anyhow::Result;
+
+/// Reports what `krunch install` has already done, without changing anything or asking for admin
+/// rights. Checks that fail are reported as unknown.
+pub async fn cli_status() -> Result<()> {
+    for step in get_steps() {
+        for check in step.check().await {
+            let state = match check.status {
+                CheckStatus::Ok(state) => state,
+                CheckStatus::Problem { description, .. } => description,
+                CheckStatus::Skipped(reason) => format!("unknown ({})", reason),
+                CheckStatus::Error(err) => format!("unknown ({})", err),
+            };
+            println!("{:<34} {}", check.label, state);
+        }
+    }
+
+    Ok(())
+}
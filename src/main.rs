@@ -1,12 +1,31 @@
+use crate::cli_bundle::cli_bundle_create;
+use crate::cli_cache::{cli_cache_list, cli_cache_prune};
+use crate::cli_doctor::cli_doctor;
 use crate::cli_install::cli_install;
+use crate::cli_lock::cli_lock;
+use crate::cli_outdated::cli_outdated;
 use crate::cli_remove::cli_remove;
+use crate::cli_restore::{cli_restore, cli_restore_list};
+use crate::cli_status::cli_status;
 use crate::cli_version::cli_version;
-use crate::shared::get_minikube_client;
+use crate::shared::shims::{get_shim_tool, run_shim};
+use crate::shared::steps::{requires_minikube, select_steps, step_name_parser};
+use crate::shared::tools::Platform;
+use crate::shared::{get_minikube_client, AdminMode};
 use anyhow::Result;
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+use std::process;
 
+mod cli_bundle;
+mod cli_cache;
+mod cli_doctor;
 mod cli_install;
+mod cli_lock;
+mod cli_outdated;
 mod cli_remove;
+mod cli_restore;
+mod cli_status;
 mod cli_version;
 mod shared;
 
@@ -19,26 +38,195 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     /// Configure everything needed for a minikube based dev setup
-    Install,
+    Install {
+        /// Download tools for this platform instead of the detected one, e.g. linux/arm64
+        #[arg(long)]
+        platform: Option<Platform>,
+        /// Install the tools from a bundle created with `krunch bundle create`, without network
+        #[arg(long)]
+        from_bundle: Option<PathBuf>,
+        /// Fail unless krunch.lock exists and matches the configured versions
+        #[arg(long, conflicts_with = "from_bundle")]
+        locked: bool,
+        /// Print what would be downloaded and changed, without changing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Run only these steps, e.g. `--only tools,dns`
+        #[arg(long, value_parser = step_name_parser(), value_delimiter = ',', conflicts_with = "skip")]
+        only: Vec<String>,
+        /// Run every step except these, e.g. `--skip tls`
+        #[arg(long, value_parser = step_name_parser(), value_delimiter = ',')]
+        skip: Vec<String>,
+        /// Keep the steps applied so far when a later one fails, instead of reverting them
+        #[arg(long)]
+        no_rollback: bool,
+        #[command(flatten)]
+        admin: AdminArgs,
+    },
+    /// Resolve the configured tools for every platform into krunch.lock
+    Lock,
+    /// Show installed tools with newer versions within and beyond their configured range
+    Outdated,
+    /// Report what `krunch install` has already set up, without changing anything
+    Status,
+    /// Find problems with the setup, explain how to repair them and optionally do so
+    Doctor {
+        /// Re-run the install steps that repair the problems found
+        #[arg(long)]
+        fix: bool,
+        #[command(flatten)]
+        admin: AdminArgs,
+    },
     /// Remove all files and configuration created by krunch
-    Remove,
+    Remove {
+        /// Print what would be removed, without changing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Keep the downloaded tools and their PATH entry
+        #[arg(long)]
+        keep_tools: bool,
+        #[command(flatten)]
+        admin: AdminArgs,
+    },
+    /// Put back a shell profile or hosts file from the backup taken before krunch modified it
+    Restore {
+        /// Id of the backup to restore, see `--list`
+        #[arg(required_unless_present = "list")]
+        id: Option<String>,
+        /// List the backups, the newest first
+        #[arg(long, conflicts_with = "id")]
+        list: bool,
+        #[command(flatten)]
+        admin: AdminArgs,
+    },
+    /// Create offline bundles of all tools for air-gapped machines
+    Bundle {
+        #[command(subcommand)]
+        command: BundleCommands,
+    },
+    /// Inspect and clean up the download cache
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommands,
+    },
     /// Display version information
     Version,
 }
 
+/// Answers the question whether to continue with admin rights up front, for scripts and CI.
+#[derive(Args)]
+struct AdminArgs {
+    /// Continue with the steps that need admin rights without asking, like KRUNCH_ASSUME_YES=1
+    #[arg(short, long)]
+    yes: bool,
+    /// Skip the steps that need admin rights without asking
+    #[arg(long, conflicts_with = "yes")]
+    no_admin: bool,
+}
+
+impl AdminArgs {
+    fn mode(&self) -> AdminMode {
+        AdminMode::from_flags(self.yes, self.no_admin)
+    }
+}
+
+#[derive(Subcommand)]
+enum BundleCommands {
+    /// Download all configured tools for a platform into a single tar.gz
+    Create {
+        /// Bundle tools for this platform instead of the detected one, e.g. linux/amd64
+        #[arg(long)]
+        platform: Option<Platform>,
+        /// Path of the bundle to write
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum CacheCommands {
+    /// List the cached downloads
+    List,
+    /// Shrink the cache to its size limit and delete unreferenced files
+    Prune {
+        /// Delete everything in the cache
+        #[arg(long)]
+        all: bool,
+    },
+}
+
+fn main() -> Result<()> {
+    // the shims in ~/.krunch/bin are links to krunch itself
+    if let Some(tool) = get_shim_tool()? {
+        process::exit(run_shim(&tool)?);
+    }
+
+    run_cli()
+}
+
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn run_cli() -> Result<()> {
     let args = Cli::parse();
 
     match &args.command {
-        Commands::Install => {
-            get_minikube_client().await?;
-            cli_install().await?
+        Commands::Install {
+            platform,
+            from_bundle,
+            locked,
+            dry_run,
+            only,
+            skip,
+            no_rollback,
+            admin,
+        } => {
+            let steps = select_steps(only, skip);
+            if requires_minikube(&steps) {
+                get_minikube_client().await?;
+            }
+            cli_install(
+                *platform,
+                from_bundle.as_deref(),
+                *locked,
+                *dry_run,
+                admin.mode(),
+                &steps,
+                !*no_rollback,
+            )
+            .await?
         }
-        Commands::Remove => {
-            get_minikube_client().await?;
-            cli_remove().await?
+        Commands::Lock => cli_lock().await?,
+        Commands::Outdated => cli_outdated().await?,
+        Commands::Status => cli_status().await?,
+        Commands::Doctor { fix, admin } => cli_doctor(*fix, admin.mode()).await?,
+        Commands::Remove {
+            dry_run,
+            keep_tools,
+            admin,
+        } => {
+            let keep: &[String] = if *keep_tools {
+                &["tools".to_string(), "path".to_string()]
+            } else {
+                &[]
+            };
+            let steps = select_steps(&[], keep);
+            if requires_minikube(&steps) {
+                get_minikube_client().await?;
+            }
+            cli_remove(*dry_run, admin.mode(), &steps).await?
         }
+        Commands::Restore { id, admin, .. } => match id {
+            Some(id) => cli_restore(id, admin.mode())?,
+            None => cli_restore_list()?,
+        },
+        Commands::Bundle { command } => match command {
+            BundleCommands::Create { platform, output } => {
+                cli_bundle_create(*platform, output).await?
+            }
+        },
+        Commands::Cache { command } => match command {
+            CacheCommands::List => cli_cache_list()?,
+            CacheCommands::Prune { all } => cli_cache_prune(*all)?,
+        },
         Commands::Version => cli_version(),
     }
 
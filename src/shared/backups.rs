@@ -0,0 +1,224 @@
+use crate::shared::config::{get_config, BackupSettings};
+use crate::shared::file_folder_paths::get_backups_folder;
+use crate::shared::since_epoch;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const BACKUP_FILE: &str = "backup.json";
+
+/// A copy of a file taken right before krunch modified it, e.g. a shell profile or the hosts file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Backup {
+    #[serde(skip)]
+    pub id: String,
+    /// Where the file is restored to.
+    pub path: PathBuf,
+    /// Milliseconds since the unix epoch.
+    pub created: u64,
+}
+
+/// Backups as `<id>/backup.json` next to the copy of the file, the id starts with the UTC time
+/// they were taken, e.g. `20261018-142301-bashrc`.
+pub struct Backups {
+    folder: PathBuf,
+    keep: usize,
+}
+
+impl Backups {
+    pub fn open(settings: &BackupSettings) -> Result<Backups> {
+        let folder = match &settings.path {
+            Some(path) => path.clone(),
+            None => get_backups_folder()?,
+        };
+
+        Ok(Backups {
+            folder,
+            keep: settings.keep,
+        })
+    }
+
+    /// Copies `path` into a new backup, then deletes the oldest backups of the same file beyond
+    /// the `keep` newest. A file that doesn't exist yet has nothing to back up.
+    pub fn back_up(&self, path: &Path) -> Result<Option<Backup>> {
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        let created = since_epoch().as_millis() as u64;
+        let file_name = file_name(path)?;
+        let prefix = format!("{}-{}", format_timestamp(created / 1000), file_name);
+        let mut id = prefix.clone();
+        let mut counter = 1;
+        while self.folder.join(&id).exists() {
+            counter += 1;
+            id = format!("{}-{}", prefix, counter);
+        }
+
+        let folder = self.folder.join(&id);
+        fs::create_dir_all(&folder)?;
+        fs::copy(path, folder.join(&file_name))?;
+        let backup = Backup {
+            id,
+            path: path.to_path_buf(),
+            created,
+        };
+        fs::write(
+            folder.join(BACKUP_FILE),
+            serde_json::to_string_pretty(&backup)?,
+        )?;
+
+        self.prune(path)?;
+
+        Ok(Some(backup))
+    }
+
+    /// All backups, the newest first.
+    pub fn list(&self) -> Result<Vec<Backup>> {
+        let mut backups = vec![];
+        if !self.folder.exists() {
+            return Ok(backups);
+        }
+
+        for entry in fs::read_dir(&self.folder)? {
+            let folder = entry?.path();
+            // a folder without a readable backup.json, e.g. from an interrupted backup, is skipped
+            let Ok(data) = fs::read_to_string(folder.join(BACKUP_FILE)) else {
+                continue;
+            };
+            let Ok(mut backup) = serde_json::from_str::<Backup>(&data) else {
+                continue;
+            };
+            backup.id = folder
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+            backups.push(backup);
+        }
+        backups.sort_by(|a, b| (Reverse(a.created), &b.id).cmp(&(Reverse(b.created), &a.id)));
+
+        Ok(backups)
+    }
+
+    pub fn get(&self, id: &str) -> Result<Backup> {
+        self.list()?
+            .into_iter()
+            .find(|backup| backup.id == id)
+            .ok_or(anyhow!("no backup {}, see `krunch restore --list`", id))
+    }
+
+    /// Contents of the file at the time of the backup.
+    pub fn read(&self, backup: &Backup) -> Result<String> {
+        let file = self.folder.join(&backup.id).join(file_name(&backup.path)?);
+        Ok(fs::read_to_string(file)?)
+    }
+
+    fn prune(&self, path: &Path) -> Result<()> {
+        let backups = self.list()?;
+        let outdated = backups
+            .iter()
+            .filter(|backup| backup.path == path)
+            .skip(self.keep);
+
+        for backup in outdated {
+            fs::remove_dir_all(self.folder.join(&backup.id))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Backs up a file krunch is about to modify with the configured retention.
+pub fn back_up(path: &Path) -> Result<Option<Backup>> {
+    Backups::open(&get_config()?.backups)?.back_up(path)
+}
+
+fn file_name(path: &Path) -> Result<String> {
+    let name = path
+        .file_name()
+        .ok_or(anyhow!("invalid file path {}", path.display()))?
+        .to_string_lossy();
+
+    Ok(name.trim_start_matches('.').to_string())
+}
+
+/// Formats seconds since the unix epoch as `YYYYMMDD-hhmmss` in UTC.
+fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+
+    // civil date from days since 1970-01-01, after Howard Hinnant's `civil_from_days`
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "19700101-000000");
+        assert_eq!(format_timestamp(951782400), "20000229-000000");
+        assert_eq!(format_timestamp(1792333381), "20261018-142301");
+    }
+
+    #[test]
+    fn test_back_up_and_prune() {
+        let dir = tempfile::tempdir().unwrap();
+        let backups = Backups {
+            folder: dir.path().join("backups"),
+            keep: 2,
+        };
+        let profile = dir.path().join(".bashrc");
+        let hosts = dir.path().join("hosts");
+
+        assert!(backups.back_up(&profile).unwrap().is_none());
+
+        fs::write(&hosts, "127.0.0.1 localhost\n").unwrap();
+        backups.back_up(&hosts).unwrap();
+        for version in 1..=3 {
+            fs::write(&profile, format!("export A={}\n", version)).unwrap();
+            backups.back_up(&profile).unwrap();
+        }
+
+        // only the two newest backups of the profile are kept, the one of hosts stays
+        let listed = backups.list().unwrap();
+        assert_eq!(listed.len(), 3);
+        let profile_backups: Vec<String> = listed
+            .iter()
+            .filter(|backup| backup.path == profile)
+            .map(|backup| backups.read(backup).unwrap())
+            .collect();
+        assert_eq!(profile_backups, ["export A=3\n", "export A=2\n"]);
+        let hosts_backup = listed.iter().find(|backup| backup.path == hosts).unwrap();
+        assert_eq!(
+            backups
+                .read(&backups.get(&hosts_backup.id).unwrap())
+                .unwrap(),
+            "127.0.0.1 localhost\n"
+        );
+        assert!(backups.get("unknown").is_err());
+    }
+}
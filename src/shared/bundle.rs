@@ -0,0 +1,241 @@
+use crate::shared::sha256_of_file;
+use crate::shared::tools::{Platform, Tool};
+use anyhow::{anyhow, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::fs::File;
+use std::path::{Component, Path, PathBuf};
+use tar::{Archive, Builder};
+use tempfile::TempDir;
+
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// Describes the content of an offline bundle, a tar.gz with the downloaded artifacts of every
+/// tool for one platform.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BundleManifest {
+    pub platform: Platform,
+    pub tools: Vec<BundledTool>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BundledTool {
+    pub name: String,
+    pub version: String,
+    /// Path of the downloaded artifact inside the bundle.
+    pub file: String,
+    pub sha256: String,
+    pub source: String,
+    /// The tool definition travels with the bundle, so that tools only defined in the config of
+    /// the creating machine can be installed as well.
+    pub definition: Tool,
+}
+
+impl BundleManifest {
+    /// Fails unless every name, version and file name is a plain file name, and the artifact
+    /// paths stay inside the bundle. They become paths under ~/.krunch, so a crafted bundle could
+    /// otherwise write or read anywhere.
+    fn check_paths(&self) -> Result<()> {
+        for tool in &self.tools {
+            check_file_name(&tool.name, "tool name")?;
+            check_file_name(&tool.version, &format!("version of {}", tool.name))?;
+            check_file_name(
+                &tool.tool().file_name(&self.platform),
+                &format!("file name of {}", tool.name),
+            )?;
+
+            let file = Path::new(&tool.file);
+            if file.components().next().is_none()
+                || !file
+                    .components()
+                    .all(|component| matches!(component, Component::Normal(_)))
+            {
+                return Err(anyhow!(
+                    "invalid artifact path '{}' of {} in bundle",
+                    tool.file,
+                    tool.name
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn check_file_name(value: &str, what: &str) -> Result<()> {
+    let mut components = Path::new(value).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(()),
+        _ => Err(anyhow!("invalid {} '{}' in bundle", what, value)),
+    }
+}
+
+impl BundledTool {
+    pub fn tool(&self) -> Tool {
+        let mut tool = self.definition.clone();
+        tool.name = self.name.clone();
+        tool
+    }
+}
+
+/// An unpacked bundle, removed again when dropped.
+pub struct Bundle {
+    dir: TempDir,
+    pub manifest: BundleManifest,
+}
+
+impl Bundle {
+    pub fn open(path: &Path) -> Result<Bundle> {
+        let dir = tempfile::tempdir()?;
+        let file = File::open(path)
+            .map_err(|err| anyhow!("failed to open bundle {}: {}", path.display(), err))?;
+        Archive::new(GzDecoder::new(file)).unpack(&dir)?;
+
+        let manifest_path = dir.path().join(MANIFEST_FILE);
+        if !manifest_path.exists() {
+            return Err(anyhow!("{} is not a krunch bundle", path.display()));
+        }
+        let manifest: BundleManifest = serde_json::from_str(&fs::read_to_string(manifest_path)?)?;
+        manifest.check_paths()?;
+
+        Ok(Bundle { dir, manifest })
+    }
+
+    /// Path of a tool's artifact in the unpacked bundle, after checking it against the digest
+    /// recorded when the bundle was created.
+    pub fn verified_artifact(&self, tool: &BundledTool) -> Result<PathBuf> {
+        let artifact = self.dir.path().join(&tool.file);
+        let actual = sha256_of_file(&artifact)?;
+        if actual != tool.sha256 {
+            return Err(anyhow!(
+                "checksum mismatch for {} in bundle: expected {}, got {}",
+                tool.file,
+                tool.sha256,
+                actual
+            ));
+        }
+
+        Ok(artifact)
+    }
+}
+
+/// Writes the manifest into `staging` and packs the folder into a tar.gz at `output`.
+pub fn write_bundle(staging: &Path, manifest: &BundleManifest, output: &Path) -> Result<()> {
+    fs::write(
+        staging.join(MANIFEST_FILE),
+        serde_json::to_string_pretty(manifest)?,
+    )?;
+
+    let encoder = GzEncoder::new(File::create(output)?, Compression::default());
+    let mut builder = Builder::new(encoder);
+    builder.append_dir_all(".", staging)?;
+    builder.into_inner()?.finish()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let staging = dir.path().join("staging");
+        fs::create_dir_all(staging.join("stern")).unwrap();
+        fs::write(staging.join("stern/stern.tar.gz"), b"artifact").unwrap();
+        fs::write(staging.join("stern/tampered.tar.gz"), b"modified").unwrap();
+
+        let definition: Tool = serde_json::from_str(
+            r#"{
+                "url": "https://example.com/stern_{version}_{os}_{arch}{ext}",
+                "ext": ".tar.gz",
+                "archive": "tar.gz"
+            }"#,
+        )
+        .unwrap();
+        let bundled = |file: &str| BundledTool {
+            name: "stern".to_string(),
+            version: "1.28.0".to_string(),
+            file: format!("stern/{}", file),
+            sha256: sha256_of_file(&staging.join("stern/stern.tar.gz")).unwrap(),
+            source: "https://example.com/stern_1.28.0_linux_amd64.tar.gz".to_string(),
+            definition: definition.clone(),
+        };
+        let manifest = BundleManifest {
+            platform: Platform::from_str("linux/amd64").unwrap(),
+            tools: vec![bundled("stern.tar.gz"), bundled("tampered.tar.gz")],
+        };
+        let output = dir.path().join("bundle.tar.gz");
+        write_bundle(&staging, &manifest, &output).unwrap();
+
+        let bundle = Bundle::open(&output).unwrap();
+        assert_eq!(bundle.manifest.platform, manifest.platform);
+        // the tool isn't built in, its definition comes from the bundle
+        let tool = bundle.manifest.tools[0].tool();
+        assert_eq!(tool.name, "stern");
+        assert_eq!(tool.url, definition.url);
+        let artifact = bundle.verified_artifact(&bundle.manifest.tools[0]).unwrap();
+        assert_eq!(fs::read(artifact).unwrap(), b"artifact");
+        let err = bundle
+            .verified_artifact(&bundle.manifest.tools[1])
+            .unwrap_err();
+        assert!(err.to_string().contains("checksum mismatch"));
+
+        assert!(Bundle::open(&staging.join("stern/stern.tar.gz")).is_err());
+    }
+
+    #[test]
+    fn test_reject_paths_outside_krunch() {
+        let dir = tempfile::tempdir().unwrap();
+        let staging = dir.path().join("staging");
+        fs::create_dir_all(staging.join("stern")).unwrap();
+        fs::write(staging.join("stern/stern.tar.gz"), b"artifact").unwrap();
+
+        let bundled = |name: &str, version: &str, file: &str, definition: &str| BundledTool {
+            name: name.to_string(),
+            version: version.to_string(),
+            file: file.to_string(),
+            sha256: sha256_of_file(&staging.join("stern/stern.tar.gz")).unwrap(),
+            source: "https://example.com/stern.tar.gz".to_string(),
+            definition: serde_json::from_str(definition).unwrap(),
+        };
+        let url = r#"{ "url": "https://example.com/stern.tar.gz" }"#;
+        let cases = [
+            bundled(
+                "stern",
+                "../../../../.bashrc-dir",
+                "stern/stern.tar.gz",
+                url,
+            ),
+            bundled("../bin", "1.28.0", "stern/stern.tar.gz", url),
+            bundled("stern", "1.28.0", "/etc/passwd", url),
+            bundled("stern", "1.28.0", "stern/../../outside", url),
+            bundled("stern", "", "stern/stern.tar.gz", url),
+            bundled(
+                "stern",
+                "1.28.0",
+                "stern/stern.tar.gz",
+                r#"{ "url": "https://example.com/stern.tar.gz", "file": "../../.bashrc" }"#,
+            ),
+        ];
+
+        for (index, tool) in cases.into_iter().enumerate() {
+            let manifest = BundleManifest {
+                platform: Platform::from_str("linux/amd64").unwrap(),
+                tools: vec![tool],
+            };
+            let output = dir.path().join(format!("bundle-{}.tar.gz", index));
+            write_bundle(&staging, &manifest, &output).unwrap();
+
+            let err = Bundle::open(&output)
+                .err()
+                .expect("malicious bundle was opened");
+            assert!(err.to_string().contains("in bundle"), "{}", err);
+        }
+    }
+}
@@ -0,0 +1,325 @@
+use crate::shared::config::CacheSettings;
+use crate::shared::file_folder_paths::get_cache_folder;
+use crate::shared::{is_sha256, sha256_of_file, since_epoch};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+const INDEX_FILE: &str = "index.json";
+const BLOBS_FOLDER: &str = "blobs";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub sha256: String,
+    pub size: u64,
+    /// Seconds since the unix epoch.
+    pub last_used: u64,
+    /// Whether the download matched a pinned, locked or published checksum.
+    #[serde(default)]
+    pub verified: bool,
+}
+
+/// A blob whose contents were just checked against its name.
+pub struct CachedFile {
+    pub path: PathBuf,
+    pub sha256: String,
+    pub verified: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheIndex {
+    /// Cached downloads keyed by url, several urls may share the same blob.
+    entries: BTreeMap<String, CacheEntry>,
+}
+
+/// Content-addressed store of downloaded files. Blobs are named by their SHA-256 digest, the
+/// index maps download urls to them.
+pub struct Cache {
+    folder: PathBuf,
+    max_size: u64,
+}
+
+impl Cache {
+    pub fn open(settings: &CacheSettings) -> Result<Cache> {
+        let folder = match &settings.path {
+            Some(path) => path.clone(),
+            None => get_cache_folder()?,
+        };
+
+        Ok(Cache {
+            folder,
+            max_size: settings.max_size_mb * 1024 * 1024,
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_size > 0
+    }
+
+    pub fn folder(&self) -> &Path {
+        &self.folder
+    }
+
+    /// Returns the cached file for a url, looked up by its expected digest first if it is known,
+    /// which makes it verified. A blob whose contents don't match its name is dropped.
+    pub fn lookup(&self, url: &str, expected: Option<&str>) -> Result<Option<CachedFile>> {
+        if !self.is_enabled() {
+            return Ok(None);
+        }
+
+        let mut index = self.read_index()?;
+
+        let (sha256, verified) = match (expected, index.entries.get(url)) {
+            (Some(expected), _) => (expected.to_lowercase(), true),
+            (None, Some(entry)) => (entry.sha256.clone(), entry.verified),
+            (None, None) => return Ok(None),
+        };
+
+        let blob = self.blob_path(&sha256)?;
+        if !blob.exists() {
+            return Ok(None);
+        }
+        if sha256_of_file(&blob)? != sha256 {
+            fs::remove_file(&blob)?;
+            index.entries.retain(|_, entry| entry.sha256 != sha256);
+            self.write_index(&index)?;
+            return Ok(None);
+        }
+
+        let entry = CacheEntry {
+            size: blob.metadata()?.len(),
+            sha256: sha256.clone(),
+            last_used: since_epoch().as_secs(),
+            verified,
+        };
+        index.entries.insert(url.to_string(), entry);
+        self.write_index(&index)?;
+
+        Ok(Some(CachedFile {
+            path: blob,
+            sha256,
+            verified,
+        }))
+    }
+
+    /// Copies a downloaded file into the cache and evicts the least recently used files if the
+    /// cache grows beyond its size limit.
+    pub fn insert(&self, url: &str, file: &Path, verified: bool) -> Result<()> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+
+        let sha256 = sha256_of_file(file)?;
+        let blob = self.blob_path(&sha256)?;
+
+        if !blob.exists() {
+            let blobs_folder = self.folder.join(BLOBS_FOLDER);
+            fs::create_dir_all(&blobs_folder)?;
+            let mut tmp_file = NamedTempFile::new_in(&blobs_folder)?;
+            io::copy(&mut File::open(file)?, &mut tmp_file)?;
+            tmp_file.persist(&blob)?;
+        }
+
+        let mut index = self.read_index()?;
+        let entry = CacheEntry {
+            size: blob.metadata()?.len(),
+            sha256,
+            last_used: since_epoch().as_secs(),
+            verified,
+        };
+        index.entries.insert(url.to_string(), entry);
+        self.evict(&mut index, self.max_size, Some(url))?;
+        self.write_index(&index)?;
+
+        Ok(())
+    }
+
+    pub fn list(&self) -> Result<Vec<(String, CacheEntry)>> {
+        let mut entries: Vec<(String, CacheEntry)> =
+            self.read_index()?.entries.into_iter().collect();
+        entries.sort_by_key(|(_, entry)| Reverse(entry.last_used));
+
+        Ok(entries)
+    }
+
+    /// Shrinks the cache to `max_size` bytes and removes blobs no url refers to, returns the
+    /// number of bytes freed.
+    pub fn prune(&self, max_size: u64) -> Result<u64> {
+        let mut index = self.read_index()?;
+        let mut freed = self.evict(&mut index, max_size, None)?;
+        self.write_index(&index)?;
+
+        let referenced: Vec<&String> = index.entries.values().map(|e| &e.sha256).collect();
+        let blobs_folder = self.folder.join(BLOBS_FOLDER);
+        if blobs_folder.exists() {
+            for blob in fs::read_dir(blobs_folder)? {
+                let blob = blob?;
+                let name = blob.file_name().to_string_lossy().to_string();
+                if !referenced.contains(&&name) {
+                    freed += blob.metadata()?.len();
+                    fs::remove_file(blob.path())?;
+                }
+            }
+        }
+
+        Ok(freed)
+    }
+
+    pub fn max_size(&self) -> u64 {
+        self.max_size
+    }
+
+    /// Drops the least recently used entries, except for `keep`, until the blobs they refer to
+    /// fit into `max_size`.
+    fn evict(&self, index: &mut CacheIndex, max_size: u64, keep: Option<&str>) -> Result<u64> {
+        let mut freed = 0;
+
+        loop {
+            let mut sizes: BTreeMap<&String, u64> = BTreeMap::new();
+            for entry in index.entries.values() {
+                sizes.insert(&entry.sha256, entry.size);
+            }
+            if sizes.values().sum::<u64>() <= max_size {
+                break;
+            }
+
+            let oldest = index
+                .entries
+                .iter()
+                .filter(|(url, _)| Some(url.as_str()) != keep)
+                .min_by_key(|(_, e)| e.last_used);
+            let (url, sha256) = match oldest {
+                Some((url, entry)) => (url.clone(), entry.sha256.clone()),
+                None => break,
+            };
+            index.entries.remove(&url);
+
+            if !index.entries.values().any(|e| e.sha256 == sha256) {
+                let blob = self.blob_path(&sha256)?;
+                if blob.exists() {
+                    freed += blob.metadata()?.len();
+                    fs::remove_file(blob)?;
+                }
+            }
+        }
+
+        Ok(freed)
+    }
+
+    /// Blobs are named by their digest, anything else could point outside the cache.
+    fn blob_path(&self, sha256: &str) -> Result<PathBuf> {
+        if !is_sha256(sha256) {
+            return Err(anyhow!("invalid SHA-256 digest '{}'", sha256));
+        }
+
+        Ok(self.folder.join(BLOBS_FOLDER).join(sha256))
+    }
+
+    fn read_index(&self) -> Result<CacheIndex> {
+        let path = self.folder.join(INDEX_FILE);
+        if !path.exists() {
+            return Ok(CacheIndex::default());
+        }
+
+        // a broken index only costs a download, so it is not worth failing over
+        let mut index: CacheIndex =
+            serde_json::from_str(&fs::read_to_string(path)?).unwrap_or_default();
+        index.entries.retain(|_, entry| is_sha256(&entry.sha256));
+
+        Ok(index)
+    }
+
+    fn write_index(&self, index: &CacheIndex) -> Result<()> {
+        fs::create_dir_all(&self.folder)?;
+        let tmp_file = NamedTempFile::new_in(&self.folder)?;
+        serde_json::to_writer_pretty(&tmp_file, index)?;
+        tmp_file.persist(self.folder.join(INDEX_FILE))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_lookup_and_evict() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache {
+            folder: dir.path().join("cache"),
+            max_size: 10,
+        };
+        let download = dir.path().join("download");
+
+        fs::write(&download, b"123456").unwrap();
+        cache
+            .insert("https://example.com/a", &download, false)
+            .unwrap();
+        let sha256 = sha256_of_file(&download).unwrap();
+
+        let by_url = cache
+            .lookup("https://example.com/a", None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(fs::read(by_url.path).unwrap(), b"123456");
+        assert!(!by_url.verified);
+        let by_digest = cache
+            .lookup("https://mirror.example.com/a", Some(&sha256))
+            .unwrap()
+            .unwrap();
+        assert!(by_digest.verified);
+        assert_eq!(cache.list().unwrap().len(), 2);
+
+        // both urls share one blob, the second file pushes the cache beyond its limit
+        fs::write(&download, b"abcdef").unwrap();
+        cache
+            .insert("https://example.com/b", &download, true)
+            .unwrap();
+        assert!(cache
+            .lookup("https://example.com/a", None)
+            .unwrap()
+            .is_none());
+        assert!(cache
+            .lookup("https://example.com/b", None)
+            .unwrap()
+            .is_some());
+
+        assert_eq!(cache.prune(0).unwrap(), 6);
+        assert!(cache.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_lookup_drops_corrupted_blob() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache {
+            folder: dir.path().join("cache"),
+            max_size: 100,
+        };
+        let download = dir.path().join("download");
+        fs::write(&download, b"123456").unwrap();
+        cache
+            .insert("https://example.com/a", &download, true)
+            .unwrap();
+        let sha256 = sha256_of_file(&download).unwrap();
+
+        fs::write(cache.blob_path(&sha256).unwrap(), b"tampered").unwrap();
+        assert!(cache
+            .lookup("https://example.com/a", Some(&sha256))
+            .unwrap()
+            .is_none());
+        assert!(!cache.blob_path(&sha256).unwrap().exists());
+        assert!(cache.list().unwrap().is_empty());
+
+        // a digest that isn't one never becomes a path
+        assert!(cache
+            .lookup("https://example.com/a", Some("../../../etc/hosts"))
+            .is_err());
+    }
+}
@@ -0,0 +1,317 @@
+use crate::shared::file_folder_paths::{get_config_file_path, get_project_config_path};
+use crate::shared::mirrors::MirrorRule;
+use crate::shared::tools::Tool;
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Tool versions keyed by tool name, each an exact version, a semver range like `~1.28` or `latest`.
+pub type Versions = BTreeMap<String, String>;
+
+#[derive(Debug, Deserialize)]
+pub struct KrunchConfig {
+    #[serde(default)]
+    pub versions: Versions,
+    /// Additional tool definitions, a definition named like a built-in tool replaces it.
+    #[serde(default)]
+    pub tools: BTreeMap<String, Tool>,
+    /// Pinned SHA-256 digests keyed by download url, preferred over published checksums.
+    #[serde(default)]
+    pub checksums: BTreeMap<String, String>,
+    /// Refuse to install downloads for which no checksum is pinned, locked or published.
+    #[serde(default = "default_require_checksums")]
+    pub require_checksums: bool,
+    /// Trust downloads without any checksum on first use and record their digest, which later
+    /// downloads of the same url then have to match. Off unless the user opts in.
+    #[serde(default)]
+    pub record_checksums: bool,
+    /// Number of tools downloaded at the same time.
+    #[serde(default = "default_parallel_downloads")]
+    pub parallel_downloads: usize,
+    #[serde(default)]
+    pub download: DownloadSettings,
+    #[serde(default)]
+    pub cache: CacheSettings,
+    /// Url rewrites tried in order before the upstream url.
+    #[serde(default)]
+    pub mirrors: Vec<MirrorRule>,
+    /// Whether the upstream url is tried once all mirrors failed. Without it, a download no mirror
+    /// matches fails instead of going upstream.
+    #[serde(default = "default_upstream_fallback")]
+    pub upstream_fallback: bool,
+    #[serde(default)]
+    pub releases: ReleaseSettings,
+    #[serde(default)]
+    pub backups: BackupSettings,
+    /// Keys besides `versions` that a project's `.krunch.json` may set, only read from the user
+    /// config.
+    #[serde(default)]
+    pub project_overrides: Vec<String>,
+}
+
+/// Timeouts in seconds, the number of retries and the network setup for every HTTP request.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DownloadSettings {
+    pub connect_timeout: u64,
+    pub read_timeout: u64,
+    pub retries: u32,
+    /// Proxy for all requests, `HTTPS_PROXY` and `HTTP_PROXY` are used if it is not set.
+    pub proxy: Option<String>,
+    /// PEM file with additional trusted root certificates, e.g. of a TLS-intercepting proxy.
+    pub ca_bundle: Option<PathBuf>,
+    /// Trust the certificates of the operating system's store as well.
+    pub use_system_certs: bool,
+}
+
+impl Default for DownloadSettings {
+    fn default() -> Self {
+        DownloadSettings {
+            connect_timeout: 10,
+            read_timeout: 30,
+            retries: 3,
+            proxy: None,
+            ca_bundle: None,
+            use_system_certs: false,
+        }
+    }
+}
+
+impl Default for KrunchConfig {
+    fn default() -> Self {
+        serde_json::from_str("{}").expect("empty config is valid")
+    }
+}
+
+/// Location and size limit of the download cache, a `max_size_mb` of 0 disables it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CacheSettings {
+    pub path: Option<PathBuf>,
+    pub max_size_mb: u64,
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        CacheSettings {
+            path: None,
+            max_size_mb: 2048,
+        }
+    }
+}
+
+/// Location of the backups of files krunch modifies, and how many of them are kept per file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BackupSettings {
+    pub path: Option<PathBuf>,
+    pub keep: usize,
+}
+
+impl Default for BackupSettings {
+    fn default() -> Self {
+        BackupSettings {
+            path: None,
+            keep: 10,
+        }
+    }
+}
+
+/// Where version ranges and `latest` are resolved, and for how many hours the release lists of
+/// the tools are cached.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ReleaseSettings {
+    pub github_api: String,
+    pub cache_hours: u64,
+}
+
+impl Default for ReleaseSettings {
+    fn default() -> Self {
+        ReleaseSettings {
+            github_api: "https://api.github.com".to_string(),
+            cache_hours: 24,
+        }
+    }
+}
+
+/// Reads the user config and merges the nearest project config over it. The project's
+/// `versions` replace the user's completely, so a repository gets exactly the tools it pins.
+///
+/// A checked-in project config is untrusted, anything besides `versions`, e.g. tool urls or
+/// mirrors, could put other binaries on the PATH. So other keys are rejected unless the user
+/// config lists them in `project_overrides`.
+pub fn get_config() -> Result<KrunchConfig> {
+    let mut config = read_config(&get_config_file_path()?)?;
+
+    if let Some(path) = get_project_config_path()? {
+        let project = read_config(&path)?;
+        let allowed = serde_json::from_value::<KrunchConfig>(config.clone())?.project_overrides;
+        check_project_keys(&project, &allowed)
+            .map_err(|err| anyhow!("{}: {}", path.display(), err))?;
+        merge(&mut config, project);
+    }
+
+    Ok(serde_json::from_value(config)?)
+}
+
+fn check_project_keys(project: &Value, allowed: &[String]) -> Result<()> {
+    let Value::Object(project) = project else {
+        return Ok(());
+    };
+
+    for key in project.keys() {
+        if key == "versions" || (key != "project_overrides" && allowed.contains(key)) {
+            continue;
+        }
+        return Err(anyhow!(
+            "`{}` is not allowed, a project config may only set `versions` unless \
+            `\"project_overrides\": [\"{}\"]` in {} allows more",
+            key,
+            key,
+            get_config_file_path()
+                .map(|path| path.display().to_string())
+                .unwrap_or("the user config".to_string())
+        ));
+    }
+
+    Ok(())
+}
+
+fn read_config(path: &Path) -> Result<Value> {
+    if !path.exists() {
+        return Ok(Value::Object(Default::default()));
+    }
+
+    let contents = fs::read_to_string(path)?;
+    let parse_error = |err| anyhow!("failed to parse {}: {}", path.display(), err);
+    // parsed twice, so that mistakes are reported for the file they are in
+    serde_json::from_str::<KrunchConfig>(&contents).map_err(parse_error)?;
+
+    serde_json::from_str(&contents).map_err(parse_error)
+}
+
+fn merge(config: &mut Value, project: Value) {
+    let (Value::Object(config), Value::Object(project)) = (config, project) else {
+        return;
+    };
+
+    for (key, value) in project {
+        match config.get_mut(&key) {
+            Some(existing) if key != "versions" => merge_value(existing, value),
+            _ => {
+                config.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Merges objects key by key, any other value is replaced.
+fn merge_value(existing: &mut Value, value: Value) {
+    match (existing, value) {
+        (Value::Object(existing), Value::Object(value)) => {
+            for (key, value) in value {
+                match existing.get_mut(&key) {
+                    Some(entry) => merge_value(entry, value),
+                    None => {
+                        existing.insert(key, value);
+                    }
+                }
+            }
+        }
+        (existing, value) => *existing = value,
+    }
+}
+
+fn default_parallel_downloads() -> usize {
+    4
+}
+
+fn default_upstream_fallback() -> bool {
+    true
+}
+
+fn default_require_checksums() -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::tools::{Archive, Platform, TargetArch, TargetOs};
+
+    #[test]
+    fn test_parse_extra_tool() {
+        let config: KrunchConfig = serde_json::from_str(
+            r#"{
+                "versions": { "kubectl": "1.28.3", "stern": "1.28.0" },
+                "tools": {
+                    "stern": {
+                        "url": "https://github.com/stern/stern/releases/download/v{version}/stern_{version}_{os}_{arch}{ext}",
+                        "ext": ".tar.gz",
+                        "archive": "tar.gz",
+                        "version": { "args": ["--version"], "regex": "version: (\\S+)" }
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        let platform = Platform {
+            os: TargetOs::Linux,
+            arch: TargetArch::Arm64,
+        };
+        let stern = &config.tools["stern"];
+
+        assert_eq!(config.versions["stern"], "1.28.0");
+        assert_eq!(
+            stern.url(&platform, "1.28.0").unwrap().as_str(),
+            "https://github.com/stern/stern/releases/download/v1.28.0/stern_1.28.0_linux_arm64.tar.gz"
+        );
+        assert_eq!(stern.archive(&platform), Archive::TarGz);
+    }
+
+    #[test]
+    fn test_merge_project_config() {
+        let mut config = serde_json::json!({
+            "versions": { "kubectl": "1.28.3", "helm": "3.14.4" },
+            "download": { "retries": 5, "connect_timeout": 20 },
+            "mirrors": [{ "from": "https://a/", "to": "https://b/" }]
+        });
+        merge(
+            &mut config,
+            serde_json::json!({
+                "versions": { "kubectl": "1.27.0" },
+                "download": { "retries": 1 },
+                "mirrors": []
+            }),
+        );
+        let config: KrunchConfig = serde_json::from_value(config).unwrap();
+
+        assert_eq!(config.versions.len(), 1);
+        assert_eq!(config.versions["kubectl"], "1.27.0");
+        assert_eq!(config.download.retries, 1);
+        assert_eq!(config.download.connect_timeout, 20);
+        assert!(config.mirrors.is_empty());
+    }
+
+    #[test]
+    fn test_check_project_keys() {
+        let project = serde_json::json!({
+            "versions": { "kubectl": "1.27.0" },
+            "mirrors": [{ "from": "https://a/", "to": "https://evil/" }]
+        });
+
+        assert!(check_project_keys(&serde_json::json!({ "versions": {} }), &[]).is_ok());
+        assert!(check_project_keys(&project, &[]).is_err());
+        assert!(check_project_keys(&project, &["mirrors".to_string()]).is_ok());
+        // a project can't widen what it may override itself
+        assert!(check_project_keys(
+            &serde_json::json!({ "project_overrides": ["tools"] }),
+            &["project_overrides".to_string()]
+        )
+        .is_err());
+    }
+}
@@ -1,37 +1,18 @@
+use crate::shared::tools::get_tools;
 use anyhow::{anyhow, Result};
 use std::env;
 use std::path::PathBuf;
 
-pub enum Binary {
-    Docker,
-    Buildx,
-    Kubectl,
-    Helm,
-    Skaffold,
-    K9S,
-    Mkcert,
-    Minikube,
-}
-
-pub fn get_binary_path(binary: Binary) -> Result<PathBuf> {
-    let extension = if cfg!(target_os = "windows") {
-        ".exe"
-    } else {
-        ""
-    };
+const PROJECT_CONFIG_FILE: &str = ".krunch.json";
+const LOCK_FILE: &str = "krunch.lock";
 
-    let path = match binary {
-        Binary::Docker => get_bin_folder()?.join(format!("docker{}", extension)),
-        Binary::Buildx => get_buildx_folder()?.join(format!("docker-buildx{}", extension)),
-        Binary::Kubectl => get_bin_folder()?.join(format!("kubectl{}", extension)),
-        Binary::Helm => get_bin_folder()?.join(format!("helm{}", extension)),
-        Binary::Skaffold => get_bin_folder()?.join(format!("skaffold{}", extension)),
-        Binary::K9S => get_bin_folder()?.join(format!("k9s{}", extension)),
-        Binary::Mkcert => get_bin_folder()?.join(format!("mkcert{}", extension)),
-        Binary::Minikube => PathBuf::from("minikube"),
-    };
-
-    Ok(path)
+/// Returns the installed path of a tool known to krunch, or the bare name for anything else
+/// (e.g. minikube) so that it is looked up on the PATH.
+pub fn get_binary_path(name: &str) -> Result<PathBuf> {
+    match get_tools()?.get(name) {
+        Some(tool) => tool.install_path(),
+        None => Ok(PathBuf::from(name)),
+    }
 }
 
 pub fn get_krunch_folder() -> Result<PathBuf> {
@@ -44,11 +25,68 @@ pub fn get_bin_folder() -> Result<PathBuf> {
     Ok(home_dir.join(".krunch/bin"))
 }
 
+/// Folder holding every installed tool version as `<tool>/<version>/<file>`.
+pub fn get_tools_folder() -> Result<PathBuf> {
+    let home_dir = home::home_dir().ok_or(anyhow!("failed to detect home directory"))?;
+    Ok(home_dir.join(".krunch/tools"))
+}
+
 pub fn get_config_file_path() -> Result<PathBuf> {
     let home_dir = home::home_dir().ok_or(anyhow!("failed to detect home directory"))?;
     Ok(home_dir.join(".krunch/config.json"))
 }
 
+/// Returns the nearest `.krunch.json` in the current directory or one of its parents.
+pub fn get_project_config_path() -> Result<Option<PathBuf>> {
+    let current_dir = env::current_dir()?;
+
+    Ok(current_dir
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG_FILE))
+        .find(|path| path.is_file()))
+}
+
+/// The lock file sits next to the project config, or in ~/.krunch without one.
+pub fn get_lock_file_path() -> Result<PathBuf> {
+    let folder = match get_project_config_path()? {
+        Some(path) => path
+            .parent()
+            .ok_or(anyhow!("invalid project config path {}", path.display()))?
+            .to_path_buf(),
+        None => get_krunch_folder()?,
+    };
+
+    Ok(folder.join(LOCK_FILE))
+}
+
+pub fn get_releases_file_path() -> Result<PathBuf> {
+    let home_dir = home::home_dir().ok_or(anyhow!("failed to detect home directory"))?;
+    Ok(home_dir.join(".krunch/releases.json"))
+}
+
+/// Records what `krunch install` changed, so that `krunch remove` can undo exactly that.
+pub fn get_state_file_path() -> Result<PathBuf> {
+    let home_dir = home::home_dir().ok_or(anyhow!("failed to detect home directory"))?;
+    Ok(home_dir.join(".krunch/state.json"))
+}
+
+/// Digests of downloads without a pinned, locked or published checksum, recorded on first use.
+pub fn get_recorded_checksums_path() -> Result<PathBuf> {
+    let home_dir = home::home_dir().ok_or(anyhow!("failed to detect home directory"))?;
+    Ok(home_dir.join(".krunch/checksums.json"))
+}
+
+pub fn get_cache_folder() -> Result<PathBuf> {
+    let home_dir = home::home_dir().ok_or(anyhow!("failed to detect home directory"))?;
+    Ok(home_dir.join(".krunch/cache"))
+}
+
+/// Copies of the shell profile and hosts file taken before krunch modified them.
+pub fn get_backups_folder() -> Result<PathBuf> {
+    let home_dir = home::home_dir().ok_or(anyhow!("failed to detect home directory"))?;
+    Ok(home_dir.join(".krunch/backups"))
+}
+
 pub fn get_buildx_folder() -> Result<PathBuf> {
     let home_dir = home::home_dir().ok_or(anyhow!("failed to detect home directory"))?;
     Ok(home_dir.join(".docker/cli-plugins"))
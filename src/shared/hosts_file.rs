@@ -0,0 +1,336 @@
+use crate::shared::managed_block::{BLOCK_END, BLOCK_START};
+use crate::shared::{EntryState, LINE_ENDING};
+use std::fmt;
+
+/// The hosts file as a list of lines, each keeping its text and line ending, so that writing it
+/// back reproduces everything krunch didn't edit byte for byte.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostsFile {
+    lines: Vec<Line>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Line {
+    text: String,
+    ending: String,
+    kind: LineKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum LineKind {
+    Entry(Entry),
+    /// Comments, including krunch's block markers, and lines that aren't entries.
+    Other,
+    Blank,
+}
+
+/// An IP with its host names, e.g. `127.0.0.1 localhost localhost.localdomain`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub ip: String,
+    pub hosts: Vec<String>,
+}
+
+impl Entry {
+    /// Parses the entry of a line, ignoring a trailing comment. `None` for a comment, a blank
+    /// line or an IP without host names.
+    pub fn parse(line: &str) -> Option<Entry> {
+        let mut fields = line.split('#').next()?.split_whitespace();
+        let ip = fields.next()?.to_string();
+        let hosts: Vec<String> = fields.map(str::to_string).collect();
+
+        if hosts.is_empty() {
+            return None;
+        }
+
+        Some(Entry { ip, hosts })
+    }
+
+    /// Host names are case-insensitive, and only whole names match, so `k8s.local` doesn't
+    /// match `k8s.local.example.com`.
+    pub fn has_host(&self, host: &str) -> bool {
+        self.hosts
+            .iter()
+            .any(|other| other.eq_ignore_ascii_case(host))
+    }
+}
+
+impl HostsFile {
+    pub fn parse(data: &str) -> HostsFile {
+        let lines = data
+            .split_inclusive('\n')
+            .map(|line| {
+                let text = line.trim_end_matches(['\r', '\n']);
+                Line {
+                    text: text.to_string(),
+                    ending: line[text.len()..].to_string(),
+                    kind: if text.trim().is_empty() {
+                        LineKind::Blank
+                    } else if let Some(entry) = Entry::parse(text) {
+                        LineKind::Entry(entry)
+                    } else {
+                        LineKind::Other
+                    },
+                }
+            })
+            .collect();
+
+        HostsFile { lines }
+    }
+
+    /// The IP `host` resolves to, which is the first entry that names it.
+    pub fn resolve(&self, host: &str) -> Option<&str> {
+        self.entries()
+            .find(|entry| entry.has_host(host))
+            .map(|entry| entry.ip.as_str())
+    }
+
+    /// Whether `host` resolves to `ip`, to another IP or isn't listed at all.
+    pub fn state(&self, host: &str, ip: &str) -> EntryState {
+        match self.resolve(host) {
+            Some(current) if current == ip => EntryState::UpToDate,
+            Some(_) => EntryState::Outdated,
+            None => EntryState::Missing,
+        }
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> {
+        self.lines.iter().filter_map(|line| match &line.kind {
+            LineKind::Entry(entry) => Some(entry),
+            _ => None,
+        })
+    }
+
+    pub fn has_block(&self) -> bool {
+        self.find_block().is_some()
+    }
+
+    /// Replaces the lines of krunch's block in place, appends a new block at the end if there is
+    /// none, or removes the block if `lines` is empty. New lines use the file's line endings.
+    /// Appended to a file without a final line ending, the block doesn't get one either, so that
+    /// removing it restores the file byte for byte.
+    pub fn set_block(&mut self, lines: &[String]) {
+        let ending = self
+            .lines
+            .iter()
+            .map(|line| line.ending.as_str())
+            .find(|ending| !ending.is_empty())
+            .unwrap_or(LINE_ENDING)
+            .to_string();
+
+        let mut block = vec![];
+        if !lines.is_empty() {
+            block.push(BLOCK_START.to_string());
+            block.extend(lines.iter().cloned());
+            block.push(BLOCK_END.to_string());
+        }
+        let mut block: Vec<Line> = block
+            .into_iter()
+            .map(|text| Line {
+                kind: Entry::parse(&text).map_or(LineKind::Other, LineKind::Entry),
+                text,
+                ending: ending.clone(),
+            })
+            .collect();
+
+        match self.find_block() {
+            Some((start, end)) => {
+                let at_end_without_ending = self.lines[end].ending.is_empty();
+                if at_end_without_ending {
+                    if let Some(last) = block.last_mut() {
+                        last.ending.clear();
+                    } else if start > 0 {
+                        // the block was appended to a file without a final line ending
+                        self.lines[start - 1].ending.clear();
+                    }
+                }
+                self.lines.splice(start..=end, block);
+            }
+            None if block.is_empty() => {}
+            None => {
+                if let Some(last) = self.lines.last_mut() {
+                    if last.ending.is_empty() {
+                        last.ending = ending;
+                        if let Some(end) = block.last_mut() {
+                            end.ending.clear();
+                        }
+                    }
+                }
+                self.lines.extend(block);
+            }
+        }
+    }
+
+    /// Deletes the lines outside krunch's block that a krunch version before the block wrote.
+    /// `legacy` returns how many lines, starting with the first one it is given, are such a
+    /// leftover.
+    pub fn remove_legacy_lines(&mut self, legacy: impl Fn(&[&str]) -> usize) {
+        let block = self.find_block();
+        let in_block =
+            |index: usize| block.is_some_and(|(start, end)| (start..=end).contains(&index));
+
+        let mut kept = vec![];
+        let mut index = 0;
+        while index < self.lines.len() {
+            // a leftover can't reach into the block
+            let outside: Vec<&str> = (index..self.lines.len())
+                .take_while(|index| !in_block(*index))
+                .map(|index| self.lines[index].text.as_str())
+                .collect();
+            let count = if outside.is_empty() {
+                0
+            } else {
+                legacy(&outside).min(outside.len())
+            };
+
+            if count > 0 {
+                index += count;
+            } else {
+                kept.push(self.lines[index].clone());
+                index += 1;
+            }
+        }
+
+        self.lines = kept;
+    }
+
+    /// A copy without krunch's block, i.e. what the hosts file says without krunch.
+    pub fn without_block(&self) -> HostsFile {
+        let mut hosts_file = self.clone();
+        hosts_file.set_block(&[]);
+        hosts_file
+    }
+
+    /// Indexes of the start and end marker.
+    fn find_block(&self) -> Option<(usize, usize)> {
+        let start = self
+            .lines
+            .iter()
+            .position(|line| line.text == BLOCK_START)?;
+        let end = self.lines[start..]
+            .iter()
+            .position(|line| line.text == BLOCK_END)?;
+
+        Some((start, start + end))
+    }
+}
+
+impl fmt::Display for HostsFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            write!(f, "{}{}", line.text, line.ending)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOSTS: &str = "127.0.0.1\tlocalhost\r\n\
+        ::1  localhost ip6-localhost   # loopback\r\n\
+        \r\n\
+        # 192.168.49.2\tk8s.local\r\n\
+        10.0.0.5 k8s.local.example.com registry.local\r\n\
+        \t  \r\n\
+        192.168.49.7";
+
+    #[test]
+    fn test_round_trip() {
+        let with_block = format!(
+            "127.0.0.1 localhost\r\n{}\r\n192.168.49.2\tk8s.local\r\n{}\r\n# mine\n",
+            BLOCK_START, BLOCK_END
+        );
+        for data in [
+            HOSTS,
+            "",
+            "\n",
+            "\r\n\r\n",
+            "127.0.0.1 localhost\n\n# end\n",
+            "# only a comment",
+            "10.0.0.1 a # trailing \t comment \r\n\n  \t\r\n",
+            &with_block,
+        ] {
+            let hosts_file = HostsFile::parse(data);
+            assert_eq!(hosts_file.to_string(), data);
+            assert_eq!(
+                hosts_file.without_block().without_block(),
+                hosts_file.without_block()
+            );
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        let hosts_file = HostsFile::parse(HOSTS);
+
+        assert_eq!(hosts_file.resolve("ip6-localhost"), Some("::1"));
+        assert_eq!(hosts_file.resolve("REGISTRY.local"), Some("10.0.0.5"));
+        // neither the commented entry nor the longer name count
+        assert_eq!(hosts_file.resolve("k8s.local"), None);
+        assert_eq!(hosts_file.entries().count(), 3);
+
+        let shared =
+            HostsFile::parse("192.168.49.2 registry.local k8s.local\n192.168.49.3 k8s.local");
+        assert_eq!(
+            shared.state("k8s.local", "192.168.49.2"),
+            EntryState::UpToDate
+        );
+        assert_eq!(
+            shared.state("k8s.local", "192.168.49.3"),
+            EntryState::Outdated
+        );
+    }
+
+    #[test]
+    fn test_set_block() {
+        let mut hosts_file = HostsFile::parse(HOSTS);
+        hosts_file.set_block(&["192.168.49.2\tk8s.local".to_string()]);
+
+        assert_eq!(
+            hosts_file.to_string(),
+            format!(
+                "{}\r\n{}\r\n192.168.49.2\tk8s.local\r\n{}",
+                HOSTS, BLOCK_START, BLOCK_END
+            )
+        );
+        assert_eq!(hosts_file.resolve("k8s.local"), Some("192.168.49.2"));
+        assert_eq!(hosts_file.without_block().resolve("k8s.local"), None);
+
+        hosts_file.set_block(&["192.168.49.3\tk8s.local".to_string()]);
+        assert_eq!(hosts_file.resolve("k8s.local"), Some("192.168.49.3"));
+
+        hosts_file.set_block(&[]);
+        assert!(!hosts_file.has_block());
+        assert_eq!(hosts_file.to_string(), HOSTS);
+
+        let with_final_ending = format!("{}\r\n", HOSTS);
+        let mut hosts_file = HostsFile::parse(&with_final_ending);
+        hosts_file.set_block(&["192.168.49.2\tk8s.local".to_string()]);
+        assert!(hosts_file
+            .to_string()
+            .ends_with(&format!("{}\r\n", BLOCK_END)));
+        hosts_file.set_block(&[]);
+        assert_eq!(hosts_file.to_string(), with_final_ending);
+    }
+
+    #[test]
+    fn test_remove_legacy_lines() {
+        let legacy = |lines: &[&str]| usize::from(lines[0] == "192.168.49.2\tk8s.local");
+        let data = format!(
+            "127.0.0.1 localhost\r\n192.168.49.2\tk8s.local\r\n# mine\r\n{}\r\n192.168.49.2\tk8s.local\r\n{}\r\n",
+            BLOCK_START, BLOCK_END
+        );
+
+        let mut hosts_file = HostsFile::parse(&data);
+        hosts_file.remove_legacy_lines(legacy);
+
+        // only the line outside the block goes, the rest is kept byte for byte
+        assert_eq!(
+            hosts_file.to_string(),
+            data.replacen("192.168.49.2\tk8s.local\r\n", "", 1)
+        );
+        assert_eq!(hosts_file.resolve("k8s.local"), Some("192.168.49.2"));
+    }
+}
@@ -0,0 +1,683 @@
+use crate::shared::config::DownloadSettings;
+use anyhow::{anyhow, Error, Result};
+use futures_util::StreamExt;
+use reqwest::header::{HeaderMap, CONTENT_LENGTH, CONTENT_RANGE, RANGE};
+use reqwest::{
+    Certificate, Client, ClientBuilder, NoProxy, Proxy, RequestBuilder, Response, StatusCode, Url,
+};
+use rustls::RootCertStore;
+use std::env;
+use std::error::Error as StdError;
+use std::fmt;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+use tokio::time::{sleep, timeout};
+
+const MAX_BACKOFF_SECONDS: u64 = 30;
+
+/// Builds the client for all downloads. Without a configured proxy reqwest picks up
+/// `HTTPS_PROXY`, `HTTP_PROXY` and `NO_PROXY` from the environment by itself.
+pub fn get_http_client(settings: &DownloadSettings) -> Result<Client> {
+    let mut builder = Client::builder()
+        .connect_timeout(Duration::from_secs(settings.connect_timeout))
+        // the GitHub API rejects requests without one
+        .user_agent(concat!("krunch/", env!("CARGO_PKG_VERSION")));
+
+    if let Some(proxy) = &settings.proxy {
+        let proxy = Proxy::all(proxy)
+            .map_err(|err| anyhow!("invalid proxy '{}': {}", proxy, err))?
+            .no_proxy(NoProxy::from_env());
+        builder = builder.proxy(proxy);
+    }
+
+    if let Some(ca_bundle) = &settings.ca_bundle {
+        let pem = fs::read(ca_bundle)
+            .map_err(|err| anyhow!("failed to read {}: {}", ca_bundle.display(), err))?;
+        if !String::from_utf8_lossy(&pem).contains("-----BEGIN CERTIFICATE-----") {
+            return Err(anyhow!(
+                "{} contains no PEM encoded certificate",
+                ca_bundle.display()
+            ));
+        }
+        // every certificate of the bundle is added, not just the first one
+        builder = builder.add_root_certificate(Certificate::from_pem(&pem)?);
+    }
+
+    if settings.use_system_certs {
+        let certs = rustls_native_certs::load_native_certs()
+            .map_err(|err| anyhow!("failed to load the system certificates: {}", err))?;
+        builder = add_parsable_certificates(builder, certs.into_iter().map(|cert| cert.0));
+    }
+
+    builder
+        .build()
+        .map_err(|err| anyhow!("failed to set up the http client: {}", describe(&err)))
+}
+
+/// Adds the DER encoded certificates as trusted roots, skipping the ones rustls can't parse, since
+/// a single broken certificate of the operating system shouldn't break every download.
+fn add_parsable_certificates(
+    mut builder: ClientBuilder,
+    certs: impl IntoIterator<Item = Vec<u8>>,
+) -> ClientBuilder {
+    for cert in certs {
+        let mut store = RootCertStore::empty();
+        if store.add(&rustls::Certificate(cert.clone())).is_err() {
+            continue;
+        }
+        if let Ok(cert) = Certificate::from_der(&cert) {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+
+    builder
+}
+
+/// Runs `request` until it succeeds, waiting exponentially longer between attempts. Errors that
+/// won't go away by retrying, like a 404, are returned immediately.
+pub async fn with_retries<T, F, Fut>(
+    settings: &DownloadSettings,
+    mut request: F,
+    on_retry: impl Fn(u32, &Error),
+) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut attempt = 0;
+
+    loop {
+        match request().await {
+            Ok(value) => return Ok(value),
+            Err(err) if attempt < settings.retries && is_retryable(&err) => {
+                attempt += 1;
+                on_retry(attempt, &err);
+                sleep(Duration::from_secs(backoff_seconds(attempt))).await;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+pub async fn fetch_text(client: &Client, settings: &DownloadSettings, url: &Url) -> Result<String> {
+    fetch_text_with_headers(client, settings, url, HeaderMap::new()).await
+}
+
+pub async fn fetch_text_with_headers(
+    client: &Client,
+    settings: &DownloadSettings,
+    url: &Url,
+    headers: HeaderMap,
+) -> Result<String> {
+    with_retries(
+        settings,
+        || async {
+            let request = client.get(url.clone()).headers(headers.clone());
+            let response = send(request, settings, url).await?.error_for_status()?;
+            let text = timeout(read_timeout(settings), response.text())
+                .await
+                .map_err(|_| Interrupted(format!("timed out reading from '{}'", url)))??;
+            Ok(text)
+        },
+        |_, _| (),
+    )
+    .await
+}
+
+/// Asks for the size of `url` with a HEAD request, `None` if the server doesn't tell.
+pub async fn fetch_size(
+    client: &Client,
+    settings: &DownloadSettings,
+    url: &Url,
+) -> Result<Option<u64>> {
+    with_retries(
+        settings,
+        || async {
+            let response = send(client.head(url.clone()), settings, url)
+                .await?
+                .error_for_status()?;
+            // reqwest reports the empty body of a HEAD response, not the header
+            Ok(response
+                .headers()
+                .get(CONTENT_LENGTH)
+                .and_then(|length| length.to_str().ok())
+                .and_then(|length| length.parse().ok()))
+        },
+        |_, _| (),
+    )
+    .await
+}
+
+/// Streams `url` into `path`. If `path` already holds the beginning of the file, only the
+/// remainder is requested. `on_progress` receives the downloaded and, if known, the total size.
+pub async fn fetch_to_file(
+    client: &Client,
+    settings: &DownloadSettings,
+    url: &Url,
+    path: &Path,
+    on_progress: &impl Fn(u64, Option<u64>),
+) -> Result<()> {
+    let mut existing = path.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+
+    let response = loop {
+        let mut request = client.get(url.clone());
+        if existing > 0 {
+            request = request.header(RANGE, format!("bytes={}-", existing));
+        }
+        let response = send(request, settings, url).await?;
+
+        if response.status() == StatusCode::PARTIAL_CONTENT
+            && get_range_start(&response) != Some(existing)
+        {
+            if existing == 0 {
+                return Err(anyhow!(
+                    "'{}' sent a part of the file that wasn't asked for",
+                    url
+                ));
+            }
+            // the part doesn't continue the file, so it is downloaded from the start again
+            File::create(path)?;
+            existing = 0;
+            continue;
+        }
+        break response;
+    };
+
+    if response.status() == StatusCode::RANGE_NOT_SATISFIABLE && existing > 0 {
+        // the previous attempt already received everything
+        on_progress(existing, Some(existing));
+        return Ok(());
+    }
+
+    let response = response.error_for_status()?;
+    let (mut file, mut downloaded) = if response.status() == StatusCode::PARTIAL_CONTENT {
+        (OpenOptions::new().append(true).open(path)?, existing)
+    } else {
+        (File::create(path)?, 0)
+    };
+    let total_size = response.content_length().map(|length| downloaded + length);
+    on_progress(downloaded, total_size);
+
+    let mut stream = response.bytes_stream();
+    while let Some(item) = timeout(read_timeout(settings), stream.next())
+        .await
+        .map_err(|_| Interrupted(format!("timed out reading from '{}'", url)))?
+    {
+        let chunk = item?;
+        file.write_all(&chunk)?;
+        downloaded += chunk.len() as u64;
+        on_progress(downloaded, total_size);
+    }
+
+    if let Some(total_size) = total_size {
+        if downloaded < total_size {
+            return Err(Interrupted(format!(
+                "connection to '{}' closed after {} of {} bytes",
+                url, downloaded, total_size
+            ))
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Where the part of a partial response starts, from a `Content-Range` like `bytes 6-11/12`.
+fn get_range_start(response: &Response) -> Option<u64> {
+    response
+        .headers()
+        .get(CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .strip_prefix("bytes ")?
+        .split('-')
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Sends a request and turns certificate and proxy failures into errors that say what to change.
+async fn send(request: RequestBuilder, settings: &DownloadSettings, url: &Url) -> Result<Response> {
+    // reqwest only limits connecting, a server that accepts but never answers would hang
+    let response = timeout(read_timeout(settings), request.send())
+        .await
+        .map_err(|_| Interrupted(format!("timed out waiting for '{}'", url)))?;
+    let err = match response {
+        Ok(response) => return Ok(response),
+        Err(err) => err,
+    };
+    let host = url.host_str().unwrap_or_default().to_string();
+
+    let hint = if is_certificate_error(&err) {
+        format!(
+            "the TLS certificate of '{}' is not trusted ({}), if a proxy intercepts TLS add its \
+            root certificate to download.ca_bundle or set download.use_system_certs in the config",
+            host,
+            describe(&err)
+        )
+    } else if let (true, Some(proxy)) = (err.is_connect(), get_proxy(settings, url)) {
+        format!(
+            "failed to connect to '{}' through the proxy '{}' ({}), check the proxy settings and \
+            add hosts that must be reached directly to NO_PROXY",
+            host,
+            proxy,
+            describe(&err)
+        )
+    } else {
+        return Err(err.into());
+    };
+
+    Err(Error::new(err).context(hint))
+}
+
+/// Returns the proxy that requests to `url` go through, ignoring `NO_PROXY`.
+fn get_proxy(settings: &DownloadSettings, url: &Url) -> Option<String> {
+    let vars = match url.scheme() {
+        "https" => ["HTTPS_PROXY", "https_proxy"],
+        _ => ["HTTP_PROXY", "http_proxy"],
+    };
+
+    settings.proxy.clone().or_else(|| {
+        vars.iter()
+            .filter_map(|var| env::var(var).ok())
+            .find(|proxy| !proxy.trim().is_empty())
+    })
+}
+
+/// Whether rustls rejected the certificate of the server. Its error is wrapped in io::Errors,
+/// whose `source` skips the wrapped error, so those are unwrapped directly.
+fn is_certificate_error(err: &reqwest::Error) -> bool {
+    let mut source: Option<&dyn StdError> = err.source();
+    while let Some(err) = source {
+        if let Some(rustls::Error::InvalidCertificate(_)) = err.downcast_ref::<rustls::Error>() {
+            return true;
+        }
+        source = match err.downcast_ref::<io::Error>() {
+            Some(io_err) => io_err.get_ref().map(|inner| inner as &dyn StdError),
+            None => err.source(),
+        };
+    }
+
+    false
+}
+
+/// Joins the messages of an error and its sources, reqwest keeps the interesting part, like the
+/// rejected certificate, in the innermost source.
+fn describe(err: &reqwest::Error) -> String {
+    let mut messages = vec![];
+    let mut source: Option<&dyn StdError> = err.source();
+    while let Some(err) = source {
+        let message = err.to_string();
+        // wrapping errors often repeat the message of their source
+        if !messages.iter().any(|m: &String| m.contains(&message)) {
+            messages.push(message);
+        }
+        source = err.source();
+    }
+
+    if messages.is_empty() {
+        err.to_string()
+    } else {
+        messages.join(": ")
+    }
+}
+
+/// A transfer that stopped midway, e.g. on a timeout, which a retry may get past.
+#[derive(Debug)]
+struct Interrupted(String);
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl StdError for Interrupted {}
+
+/// Only network failures are retried, anything else, e.g. a file that can't be written, would
+/// fail the same way again.
+fn is_retryable(err: &Error) -> bool {
+    if err.downcast_ref::<Interrupted>().is_some() {
+        return true;
+    }
+    let err = match err.downcast_ref::<reqwest::Error>() {
+        Some(err) => err,
+        None => return false,
+    };
+
+    match err.status() {
+        Some(status) => {
+            status.is_server_error()
+                || status == StatusCode::REQUEST_TIMEOUT
+                || status == StatusCode::TOO_MANY_REQUESTS
+        }
+        // an untrusted certificate stays untrusted
+        None => !is_certificate_error(err),
+    }
+}
+
+fn backoff_seconds(attempt: u32) -> u64 {
+    2u64.saturating_pow(attempt - 1).min(MAX_BACKOFF_SECONDS)
+}
+
+fn read_timeout(settings: &DownloadSettings) -> Duration {
+    Duration::from_secs(settings.read_timeout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const BODY: &str = "0123456789ab";
+
+    fn settings() -> DownloadSettings {
+        DownloadSettings {
+            read_timeout: 1,
+            retries: 2,
+            ..DownloadSettings::default()
+        }
+    }
+
+    /// Downloads `url` the way installs do and returns its contents and the number of retries.
+    async fn download(url: &str) -> (Result<String>, u32) {
+        let settings = settings();
+        let client = get_http_client(&settings).unwrap();
+        let url = Url::parse(url).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        let retries = Cell::new(0);
+
+        let result = with_retries(
+            &settings,
+            || fetch_to_file(&client, &settings, &url, &file, &|_, _| ()),
+            |attempt, _| retries.set(attempt),
+        )
+        .await
+        .map(|()| fs::read_to_string(&file).unwrap());
+
+        (result, retries.get())
+    }
+
+    /// Serves the first half of the body with the length of all of it and closes the connection,
+    /// then answers the retries with `rest` and returns the range each of them asked for.
+    /// wiremock can't send a truncated body.
+    fn serve_truncated(rest: &[&'static str]) -> (String, thread::JoinHandle<Vec<Option<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/file", listener.local_addr().unwrap());
+        let mut responses = vec![format!(
+            "HTTP/1.1 200 OK\r\ncontent-length: 12\r\n\r\n{}",
+            &BODY[..6]
+        )];
+        responses.extend(rest.iter().map(|response| response.to_string()));
+
+        let server = thread::spawn(move || {
+            let mut ranges = vec![];
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = String::new();
+                for line in BufReader::new(&stream).lines() {
+                    let line = line.unwrap();
+                    if line.is_empty() {
+                        break;
+                    }
+                    request.push_str(&line.to_lowercase());
+                    request.push('\n');
+                }
+                ranges.push(
+                    request
+                        .lines()
+                        .find_map(|line| line.strip_prefix("range: "))
+                        .map(str::to_string),
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+            ranges.remove(0);
+            ranges
+        });
+
+        (url, server)
+    }
+
+    #[tokio::test]
+    async fn test_resume_with_range() {
+        let (url, server) =
+            serve_truncated(&["HTTP/1.1 206 Partial Content\r\ncontent-length: 6\r\n\
+             content-range: bytes 6-11/12\r\n\r\n6789ab"]);
+
+        let (result, retries) = download(&url).await;
+        assert_eq!(result.unwrap(), BODY);
+        assert_eq!(retries, 1);
+        assert_eq!(server.join().unwrap(), [Some("bytes=6-".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn test_resume_without_range_support() {
+        // the server ignores the range and sends everything again
+        let (url, server) =
+            serve_truncated(&["HTTP/1.1 200 OK\r\ncontent-length: 12\r\n\r\n0123456789ab"]);
+
+        let (result, retries) = download(&url).await;
+        assert_eq!(result.unwrap(), BODY);
+        assert_eq!(retries, 1);
+        assert_eq!(server.join().unwrap(), [Some("bytes=6-".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn test_resume_with_other_range() {
+        // the part doesn't start where the file ends, so it is downloaded again without a range
+        let (url, server) = serve_truncated(&[
+            "HTTP/1.1 206 Partial Content\r\ncontent-length: 4\r\n\
+             content-range: bytes 8-11/12\r\n\r\n89ab",
+            "HTTP/1.1 200 OK\r\ncontent-length: 12\r\n\r\n0123456789ab",
+        ]);
+
+        let (result, retries) = download(&url).await;
+        assert_eq!(result.unwrap(), BODY);
+        assert_eq!(retries, 1);
+        assert_eq!(server.join().unwrap(), [Some("bytes=6-".to_string()), None]);
+    }
+
+    #[tokio::test]
+    async fn test_retries() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/file"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/file"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(BODY))
+            .mount(&server)
+            .await;
+
+        let (result, retries) = download(&format!("{}/file", server.uri())).await;
+        assert_eq!(result.unwrap(), BODY);
+        assert_eq!(retries, 1);
+
+        // a missing file stays missing
+        let server = MockServer::start().await;
+        let (result, retries) = download(&format!("{}/file", server.uri())).await;
+        assert!(result.unwrap_err().to_string().contains("404"));
+        assert_eq!(retries, 0);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_size() {
+        let server = MockServer::start().await;
+        Mock::given(method("HEAD"))
+            .and(path("/file"))
+            .respond_with(ResponseTemplate::new(200).insert_header("content-length", "1234"))
+            .mount(&server)
+            .await;
+
+        let settings = settings();
+        let client = get_http_client(&settings).unwrap();
+        let url = Url::parse(&format!("{}/file", server.uri())).unwrap();
+        assert_eq!(
+            fetch_size(&client, &settings, &url).await.unwrap(),
+            Some(1234)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/file"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(BODY)
+                    .set_delay(Duration::from_secs(5)),
+            )
+            .mount(&server)
+            .await;
+
+        let (result, retries) = download(&format!("{}/file", server.uri())).await;
+        assert!(result.unwrap_err().to_string().contains("timed out"));
+        assert_eq!(retries, 2);
+    }
+
+    const CA: &str = include_str!("testdata/ca.pem");
+
+    /// Answers `connections` requests over TLS with a certificate for localhost signed by `CA`.
+    fn serve_tls(connections: usize) -> String {
+        let config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![rustls::Certificate(
+                    include_bytes!("testdata/localhost.der").to_vec(),
+                )],
+                rustls::PrivateKey(include_bytes!("testdata/localhost.key.der").to_vec()),
+            )
+            .unwrap();
+        let config = Arc::new(config);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "https://localhost:{}/",
+            listener.local_addr().unwrap().port()
+        );
+
+        thread::spawn(move || {
+            for _ in 0..connections {
+                let (tcp, _) = listener.accept().unwrap();
+                let connection = rustls::ServerConnection::new(config.clone()).unwrap();
+                let mut stream = BufReader::new(rustls::StreamOwned::new(connection, tcp));
+                // a client that rejects the certificate fails the handshake here
+                let mut line = String::new();
+                while stream.read_line(&mut line).unwrap_or(0) > 2 {
+                    line.clear();
+                }
+                let response =
+                    "HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok";
+                stream
+                    .get_mut()
+                    .write_all(response.as_bytes())
+                    .unwrap_or(());
+            }
+        });
+
+        url
+    }
+
+    #[tokio::test]
+    async fn test_ca_bundle() {
+        let url = serve_tls(2);
+
+        let settings = settings();
+        let client = get_http_client(&settings).unwrap();
+        let err = fetch_text(&client, &settings, &Url::parse(&url).unwrap())
+            .await
+            .unwrap_err();
+        // an untrusted certificate isn't retried and explains how to trust it
+        assert!(err.to_string().contains("download.ca_bundle"), "{:#}", err);
+        assert!(is_certificate_error(err.downcast_ref().unwrap()));
+
+        let dir = tempfile::tempdir().unwrap();
+        let ca_bundle = dir.path().join("ca.pem");
+        fs::write(&ca_bundle, CA).unwrap();
+        let settings = DownloadSettings {
+            ca_bundle: Some(ca_bundle),
+            ..settings
+        };
+        let client = get_http_client(&settings).unwrap();
+        let text = fetch_text(&client, &settings, &Url::parse(&url).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(text, "ok");
+    }
+
+    #[test]
+    fn test_skip_unparsable_certificates() {
+        let ca = CA
+            .lines()
+            .filter(|line| !line.starts_with("-----"))
+            .collect::<String>();
+        let ca = base64::Engine::decode(&base64::engine::general_purpose::STANDARD, ca).unwrap();
+
+        let builder = add_parsable_certificates(Client::builder(), [b"broken".to_vec(), ca]);
+        assert!(builder.build().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_proxy() {
+        let proxy = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/file"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(BODY))
+            .mount(&proxy)
+            .await;
+        let url = Url::parse("http://krunch.invalid/file").unwrap();
+
+        let settings = DownloadSettings {
+            proxy: Some(proxy.uri()),
+            ..settings()
+        };
+        let client = get_http_client(&settings).unwrap();
+        assert_eq!(fetch_text(&client, &settings, &url).await.unwrap(), BODY);
+        let requests = proxy.received_requests().await.unwrap();
+        assert_eq!(requests[0].url.host_str(), Some("krunch.invalid"));
+
+        // nothing listens on the port of a listener that was dropped right away
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let settings = DownloadSettings {
+            proxy: Some(format!("http://{}", address)),
+            retries: 0,
+            ..settings
+        };
+        let client = get_http_client(&settings).unwrap();
+        let err = fetch_text(&client, &settings, &url).await.unwrap_err();
+        assert!(err.to_string().contains("through the proxy"), "{:#}", err);
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        assert!(is_retryable(&Interrupted("timed out".to_string()).into()));
+        let denied = io::Error::new(io::ErrorKind::PermissionDenied, "denied");
+        assert!(!is_retryable(&denied.into()));
+        assert!(!is_retryable(&anyhow!("checksum mismatch")));
+    }
+
+    #[test]
+    fn test_backoff_seconds() {
+        let delays: Vec<u64> = (1..=7).map(backoff_seconds).collect();
+        assert_eq!(delays, [1, 2, 4, 8, 16, 30, 30]);
+    }
+}
@@ -0,0 +1,174 @@
+use crate::shared::file_folder_paths::get_lock_file_path;
+use crate::shared::releases::VersionSpec;
+use crate::shared::tools::{Platform, Tool};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use tempfile::NamedTempFile;
+
+/// The resolved download of every configured tool for every platform, so that each machine
+/// installs the same bytes. Written by `krunch lock` to `krunch.lock`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Lockfile {
+    pub tools: BTreeMap<String, LockedTool>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LockedTool {
+    pub version: String,
+    /// Artifacts keyed by platform, e.g. `linux/amd64`.
+    pub platforms: BTreeMap<String, LockedArtifact>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockedArtifact {
+    pub url: String,
+    pub sha256: String,
+    /// Size in bytes, unless the server didn't report it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+impl Lockfile {
+    /// Reads the lock file that belongs to the config in use, if there is one.
+    pub fn read() -> Result<Option<Lockfile>> {
+        let path = get_lock_file_path()?;
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let contents = fs::read_to_string(&path)?;
+        let lock = serde_json::from_str(&contents)
+            .map_err(|err| anyhow!("failed to parse {}: {}", path.display(), err))?;
+
+        Ok(Some(lock))
+    }
+
+    pub fn write(&self) -> Result<()> {
+        let path = get_lock_file_path()?;
+        let folder = path
+            .parent()
+            .ok_or(anyhow!("invalid lock file path {}", path.display()))?;
+        fs::create_dir_all(folder)?;
+
+        let mut tmp_file = NamedTempFile::new_in(folder)?;
+        serde_json::to_writer_pretty(&mut tmp_file, self)?;
+        tmp_file.persist(&path)?;
+
+        Ok(())
+    }
+
+    /// Returns the locked artifact of a tool, as long as it was locked at `version`.
+    pub fn artifact(
+        &self,
+        name: &str,
+        version: &str,
+        platform: &Platform,
+    ) -> Option<&LockedArtifact> {
+        self.tools
+            .get(name)
+            .filter(|locked| locked.version == version)
+            .and_then(|locked| locked.platforms.get(&platform.to_string()))
+    }
+
+    /// Fails with every difference between the lock and the configured tools for `platform`,
+    /// where a configured version may be a range that accepts the locked one.
+    pub fn check(&self, configured: &[(Tool, String)], platform: &Platform) -> Result<()> {
+        let mut differences = vec![];
+
+        for (tool, version) in configured {
+            let locked = match self.tools.get(&tool.name) {
+                Some(locked) => locked,
+                None => {
+                    differences.push(format!("{} is not locked", tool.name));
+                    continue;
+                }
+            };
+
+            if !VersionSpec::parse(version).matches(&locked.version) {
+                differences.push(format!(
+                    "{} is locked at {} but configured as {}",
+                    tool.name, locked.version, version
+                ));
+                continue;
+            }
+
+            match locked.platforms.get(&platform.to_string()) {
+                Some(artifact) if artifact.url != tool.url(platform, &locked.version)?.as_str() => {
+                    differences.push(format!(
+                        "{} for {} is locked to a different url",
+                        tool.name, platform
+                    ))
+                }
+                Some(_) => (),
+                None => differences.push(format!("{} is not locked for {}", tool.name, platform)),
+            }
+        }
+
+        for name in self.tools.keys() {
+            if !configured.iter().any(|(tool, _)| &tool.name == name) {
+                differences.push(format!("{} is locked but not configured", name));
+            }
+        }
+
+        if differences.is_empty() {
+            return Ok(());
+        }
+
+        Err(anyhow!(
+            "{} is out of date, run `krunch lock` to update it: {}",
+            get_lock_file_path()?.display(),
+            differences.join(", ")
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let mut tool: Tool = serde_json::from_str(
+            r#"{
+                "url": "https://example.com/stern_{version}_{os}_{arch}",
+                "version": { "args": ["--version"], "regex": "version: (\\S+)" }
+            }"#,
+        )
+        .unwrap();
+        tool.name = "stern".to_string();
+        let platform: Platform = "linux/amd64".parse().unwrap();
+        let artifact = LockedArtifact {
+            url: "https://example.com/stern_1.28.0_linux_amd64".to_string(),
+            sha256: "abc".to_string(),
+            size: Some(3),
+        };
+        let mut lock = Lockfile::default();
+        lock.tools.insert(
+            "stern".to_string(),
+            LockedTool {
+                version: "1.28.0".to_string(),
+                platforms: BTreeMap::from([(platform.to_string(), artifact)]),
+            },
+        );
+
+        assert!(lock
+            .check(&[(tool.clone(), "1.28.0".to_string())], &platform)
+            .is_ok());
+        assert!(lock.artifact("stern", "1.28.0", &platform).is_some());
+        assert!(lock
+            .check(&[(tool.clone(), "~1.28".to_string())], &platform)
+            .is_ok());
+        assert!(lock
+            .check(&[(tool.clone(), "1.29.0".to_string())], &platform)
+            .is_err());
+        assert!(lock
+            .check(
+                &[(tool, "1.28.0".to_string())],
+                &"darwin/arm64".parse().unwrap()
+            )
+            .is_err());
+        assert!(lock.check(&[], &platform).is_err());
+    }
+}
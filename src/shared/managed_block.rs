@@ -0,0 +1,312 @@
+use crate::shared::backups::back_up;
+use crate::shared::state::{Change, State};
+use crate::shared::steps::Outcome;
+use crate::shared::{get_diff, LINE_ENDING};
+use anyhow::{anyhow, Result};
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+use tempfile::NamedTempFile;
+
+/// Marks the start of the lines krunch owns in a file it edits, e.g. a shell profile.
+pub const BLOCK_START: &str = "# >>> krunch >>>";
+pub const BLOCK_END: &str = "# <<< krunch <<<";
+
+/// Returns the lines of krunch's block, `None` if the file has no block.
+pub fn get_block(data: &str) -> Option<Vec<&str>> {
+    let range = find_block(data)?;
+
+    Some(
+        data[range]
+            .lines()
+            .map(|line| line.trim_end_matches('\r'))
+            .filter(|line| *line != BLOCK_START && *line != BLOCK_END)
+            .collect(),
+    )
+}
+
+/// Replaces the lines of krunch's block in place, appends a new block at the end if there is none,
+/// or removes the block if `lines` is empty. Everything outside the block stays as it is, and the
+/// block uses the file's line endings. Appended to a file without a final line ending, the block
+/// doesn't get one either, so that removing it restores the file byte for byte.
+pub fn set_block(data: &str, lines: &[String]) -> String {
+    let line_ending = if data.contains("\r\n") {
+        "\r\n"
+    } else if data.contains('\n') {
+        "\n"
+    } else {
+        LINE_ENDING
+    };
+
+    let block = if lines.is_empty() {
+        String::new()
+    } else {
+        let mut block = format!("{}{}", BLOCK_START, line_ending);
+        for line in lines {
+            block.push_str(line);
+            block.push_str(line_ending);
+        }
+        block.push_str(BLOCK_END);
+        block.push_str(line_ending);
+        block
+    };
+
+    match find_block(data) {
+        Some(range) if block.is_empty() && range.end == data.len() && !data.ends_with('\n') => {
+            // the block was appended to a file without a final line ending
+            let before = &data[..range.start];
+            before
+                .strip_suffix(line_ending)
+                .unwrap_or(before)
+                .to_string()
+        }
+        Some(range) if !data[range.clone()].ends_with('\n') => format!(
+            "{}{}{}",
+            &data[..range.start],
+            block.strip_suffix(line_ending).unwrap_or(&block),
+            &data[range.end..]
+        ),
+        Some(range) => format!("{}{}{}", &data[..range.start], block, &data[range.end..]),
+        None if block.is_empty() => data.to_string(),
+        None if data.is_empty() || data.ends_with('\n') => format!("{}{}", data, block),
+        None => format!(
+            "{}{}{}",
+            data,
+            line_ending,
+            block.strip_suffix(line_ending).unwrap_or(&block)
+        ),
+    }
+}
+
+/// Deletes lines that a krunch version before the block wrote outside of it. `legacy` returns which
+/// of the lines it is given, counted from the first one, are such a leftover, so that install can
+/// move them into the block. Lines before that range stay, e.g. a `# krunch` comment that another
+/// step's leftover still needs to be recognised.
+pub fn remove_legacy_lines(data: &str, legacy: impl Fn(&[&str]) -> Range<usize>) -> String {
+    let block = find_block(data);
+    let mut lines = vec![];
+    let mut offset = 0;
+    for line in data.split_inclusive('\n') {
+        let in_block = block.as_ref().is_some_and(|block| block.contains(&offset));
+        lines.push((line, in_block));
+        offset += line.len();
+    }
+
+    let mut result = String::new();
+    let mut index = 0;
+    while index < lines.len() {
+        // a leftover can't reach into the block
+        let outside: Vec<&str> = lines[index..]
+            .iter()
+            .take_while(|(_, in_block)| !in_block)
+            .map(|(line, _)| line.trim_end_matches(['\r', '\n']))
+            .collect();
+        let range = if outside.is_empty() {
+            0..0
+        } else {
+            let range = legacy(&outside);
+            range.start.min(outside.len())..range.end.min(outside.len())
+        };
+
+        if range.is_empty() {
+            result.push_str(lines[index].0);
+            index += 1;
+        } else {
+            for (line, _) in &lines[index..index + range.start] {
+                result.push_str(line);
+            }
+            index += range.end;
+        }
+    }
+
+    result
+}
+
+/// Sets the lines of `step` in krunch's block of a shell profile and records them, or with
+/// `dry_run` only returns the diff. Without `lines`, the step's lines are dropped, and the whole
+/// block with the last of them. Lines an older krunch wrote outside the block, as recognised by
+/// `legacy`, are replaced by the block.
+pub fn set_profile_lines(
+    step: &str,
+    path: &Path,
+    lines: Vec<String>,
+    legacy: impl Fn(&[&str]) -> Range<usize>,
+    dry_run: bool,
+) -> Result<Outcome> {
+    let original = fs::read_to_string(path)?;
+
+    let mut state = State::read()?;
+    let drop_lines = lines.is_empty();
+    let change = Change::ProfileLines {
+        path: path.to_path_buf(),
+        lines,
+    };
+    let data = if drop_lines {
+        state.forget(step, &change);
+        original.clone()
+    } else {
+        state.record(step, change);
+        remove_legacy_lines(&original, legacy)
+    };
+
+    let data = set_block(&data, &state.profile_lines(path));
+    if data == original {
+        return Ok(Outcome::AlreadyDone);
+    }
+    if dry_run {
+        return Ok(Outcome::Message(get_diff(path, &original, &data)));
+    }
+
+    write_atomically(path, &data)?;
+    state.write()?;
+
+    Ok(Outcome::Done)
+}
+
+/// Byte range of the block, from the start marker up to and including the line ending after the
+/// end marker.
+fn find_block(data: &str) -> Option<Range<usize>> {
+    let mut start = None;
+    let mut offset = 0;
+
+    for line in data.split_inclusive('\n') {
+        let content = line.trim_end_matches(['\r', '\n']);
+        if start.is_none() && content == BLOCK_START {
+            start = Some(offset);
+        } else if content == BLOCK_END {
+            if let Some(start) = start {
+                return Some(start..offset + line.len());
+            }
+        }
+        offset += line.len();
+    }
+
+    None
+}
+
+/// Replaces a file by writing a temporary file next to it and renaming that over it, so that it's
+/// never left half-written. Keeps the file's permissions, and writes through a symlink, e.g. to a
+/// profile kept in a dotfiles repository. The previous contents are backed up first.
+pub fn write_atomically(path: &Path, data: &str) -> Result<()> {
+    back_up(path)?;
+
+    let path = fs::canonicalize(path)?;
+    let folder = path
+        .parent()
+        .ok_or(anyhow!("invalid file path {}", path.display()))?;
+
+    let tmp_file = NamedTempFile::new_in(folder)?;
+    fs::write(tmp_file.path(), data)?;
+    fs::set_permissions(tmp_file.path(), fs::metadata(&path)?.permissions())?;
+    tmp_file.persist(&path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn test_set_block_round_trip() {
+        let original = "alias ll='ls -l'\n\n# mine\nexport DOCKER_HOST=\"tcp://other\"\n";
+        let added = set_block(original, &lines(&["export A=1", "export B=2"]));
+
+        assert_eq!(
+            added,
+            format!(
+                "{}{}\nexport A=1\nexport B=2\n{}\n",
+                original, BLOCK_START, BLOCK_END
+            )
+        );
+        assert_eq!(get_block(&added).unwrap(), ["export A=1", "export B=2"]);
+
+        // lines after the block stay where they are when it's updated
+        let edited = format!("{}alias k=kubectl\n", added);
+        let updated = set_block(&edited, &lines(&["export A=3"]));
+
+        assert_eq!(
+            updated,
+            format!(
+                "{}{}\nexport A=3\n{}\nalias k=kubectl\n",
+                original, BLOCK_START, BLOCK_END
+            )
+        );
+        assert_eq!(
+            set_block(&updated, &[]),
+            format!("{}alias k=kubectl\n", original)
+        );
+        assert_eq!(set_block(&added, &[]), original);
+    }
+
+    #[test]
+    fn test_set_block_without_final_line_ending() {
+        let original = "alias ll='ls -l'\nexport PATH=\"$HOME/bin:$PATH\"";
+        let added = set_block(original, &lines(&["export A=1"]));
+
+        assert_eq!(
+            added,
+            format!("{}\n{}\nexport A=1\n{}", original, BLOCK_START, BLOCK_END)
+        );
+
+        let updated = set_block(&added, &lines(&["export A=2"]));
+        assert_eq!(
+            updated,
+            format!("{}\n{}\nexport A=2\n{}", original, BLOCK_START, BLOCK_END)
+        );
+        assert_eq!(set_block(&updated, &[]), original);
+    }
+
+    #[test]
+    fn test_remove_legacy_lines() {
+        let legacy = |lines: &[&str]| match lines {
+            ["# krunch", "export OLD=1", "export NEXT=1", ..] => 1..2,
+            ["# krunch", "export OLD=1", ..] => 0..2,
+            ["export OLD=1", ..] => 0..1,
+            _ => 0..0,
+        };
+        let data = format!(
+            "export MINE=1\n\n# krunch\nexport OLD=1\n{}\nexport OLD=1\n{}\n",
+            BLOCK_START, BLOCK_END
+        );
+
+        // the line inside the block isn't a leftover
+        assert_eq!(
+            remove_legacy_lines(&data, legacy),
+            format!(
+                "export MINE=1\n\n{}\nexport OLD=1\n{}\n",
+                BLOCK_START, BLOCK_END
+            )
+        );
+        assert_eq!(
+            remove_legacy_lines("export MINE=1\n", legacy),
+            "export MINE=1\n"
+        );
+        // the comment stays for the line after it
+        assert_eq!(
+            remove_legacy_lines("# krunch\nexport OLD=1\nexport NEXT=1\n", legacy),
+            "# krunch\nexport NEXT=1\n"
+        );
+    }
+
+    #[test]
+    fn test_set_block_keeps_line_endings() {
+        let original = "127.0.0.1\tlocalhost\r\n# 192.168.49.2\tk8s.local\r\n";
+        let added = set_block(original, &lines(&["192.168.49.3\tk8s.local"]));
+
+        assert_eq!(
+            added,
+            format!(
+                "{}{}\r\n192.168.49.3\tk8s.local\r\n{}\r\n",
+                original, BLOCK_START, BLOCK_END
+            )
+        );
+        assert_eq!(get_block(&added).unwrap(), ["192.168.49.3\tk8s.local"]);
+        assert_eq!(set_block(&added, &[]), original);
+        assert!(get_block(original).is_none());
+    }
+}
@@ -0,0 +1,101 @@
+use anyhow::{anyhow, Result};
+use reqwest::Url;
+use serde::Deserialize;
+
+/// Rewrites urls starting with `from` to start with `to` instead, optionally only for some tools.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MirrorRule {
+    pub from: String,
+    pub to: String,
+    pub tools: Option<Vec<String>>,
+}
+
+/// Returns the urls to try for a download in order: every matching mirror as configured, then
+/// the upstream url itself unless `upstream_fallback` is disabled. Without the fallback, a url no
+/// mirror matches fails, rather than going to the upstream host after all.
+pub fn get_candidate_urls(
+    rules: &[MirrorRule],
+    upstream_fallback: bool,
+    tool_name: &str,
+    url: &Url,
+) -> Result<Vec<Url>> {
+    let mut candidates = vec![];
+
+    for rule in rules {
+        let applies_to_tool = match &rule.tools {
+            Some(tools) => tools.iter().any(|tool| tool == tool_name),
+            None => true,
+        };
+
+        if let Some(rest) = url.as_str().strip_prefix(&rule.from) {
+            if applies_to_tool {
+                let mirrored = format!("{}{}", rule.to, rest);
+                let mirrored = Url::parse(&mirrored)
+                    .map_err(|err| anyhow!("invalid mirror url '{}': {}", mirrored, err))?;
+                if !candidates.contains(&mirrored) {
+                    candidates.push(mirrored);
+                }
+            }
+        }
+    }
+
+    if upstream_fallback {
+        candidates.push(url.clone());
+    } else if candidates.is_empty() {
+        return Err(anyhow!(
+            "no mirror matches '{}' and upstream_fallback is disabled",
+            url
+        ));
+    }
+
+    Ok(candidates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_candidate_urls() {
+        let rules: Vec<MirrorRule> = serde_json::from_str(
+            r#"[
+                { "from": "https://dl.k8s.io/", "to": "https://artifactory.corp/k8s/", "tools": ["kubectl"] },
+                { "from": "https://github.com/", "to": "https://artifactory.corp/github/" },
+                { "from": "https://", "to": "https://proxy.corp/" }
+            ]"#,
+        )
+        .unwrap();
+        let k9s =
+            Url::parse("https://github.com/derailed/k9s/releases/download/v0.27.3/k9s.tar.gz")
+                .unwrap();
+
+        assert_eq!(
+            get_candidate_urls(&rules, true, "k9s", &k9s)
+                .unwrap()
+                .iter()
+                .map(Url::as_str)
+                .collect::<Vec<&str>>(),
+            vec![
+                "https://artifactory.corp/github/derailed/k9s/releases/download/v0.27.3/k9s.tar.gz",
+                "https://proxy.corp/github.com/derailed/k9s/releases/download/v0.27.3/k9s.tar.gz",
+                "https://github.com/derailed/k9s/releases/download/v0.27.3/k9s.tar.gz",
+            ]
+        );
+
+        let helm = Url::parse("https://get.helm.sh/helm-v3.14.4-linux-amd64.tar.gz").unwrap();
+        let err = get_candidate_urls(&rules[..2], false, "helm", &helm).unwrap_err();
+        assert!(err.to_string().contains(helm.as_str()), "{}", err);
+        assert_eq!(
+            get_candidate_urls(&rules[..2], true, "helm", &helm).unwrap(),
+            vec![helm.clone()]
+        );
+
+        let kubectl = Url::parse("https://dl.k8s.io/v1.28.3/bin/linux/amd64/kubectl").unwrap();
+        let candidates = get_candidate_urls(&rules[..1], false, "kubectl", &kubectl).unwrap();
+        assert_eq!(
+            candidates[0].as_str(),
+            "https://artifactory.corp/k8s/v1.28.3/bin/linux/amd64/kubectl"
+        );
+        assert_eq!(candidates.len(), 1);
+    }
+}
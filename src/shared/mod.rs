@@ -1,14 +1,32 @@
+use crate::shared::backups::back_up;
 use crate::shared::file_folder_paths::get_etc_hosts_path;
 use anyhow::{anyhow, Result};
-use kube::config;
-use std::fs;
+use kube::config::Kubeconfig;
+use sha2::{Digest, Sha256};
+use similar::TextDiff;
 use std::fs::File;
-use std::io::{stdin, stdout, Read, Write};
+use std::io::{self, stdin, stdout, IsTerminal, Read, Write};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Output};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{env, fs};
 use tempfile::Builder;
 
+pub mod backups;
+pub mod bundle;
+pub mod cache;
+pub mod config;
 pub mod file_folder_paths;
+pub mod hosts_file;
+pub mod http;
+pub mod lock;
+pub mod managed_block;
+pub mod mirrors;
+pub mod releases;
+pub mod shims;
+pub mod state;
+pub mod steps;
+pub mod tools;
 pub mod windows_registry;
 
 #[cfg(windows)]
@@ -17,6 +35,17 @@ pub const LINE_ENDING: &str = "\r\n";
 pub const LINE_ENDING: &str = "\n";
 pub const MINIKUBE_HOST: &str = "k8s.local";
 pub const TLS_SECRET: &str = "tls";
+const ASSUME_YES_VARIABLE: &str = "KRUNCH_ASSUME_YES";
+
+/// How an entry krunch writes, e.g. into the shell profile or /etc/hosts, compares to the
+/// current minikube.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryState {
+    UpToDate,
+    /// The entry exists, but points to an old minikube IP.
+    Outdated,
+    Missing,
+}
 
 pub fn handle_output(output: Output) -> Result<String> {
     let stdout = String::from_utf8(output.stdout.to_vec())?;
@@ -38,14 +67,43 @@ pub fn handle_output(output: Output) -> Result<String> {
     Ok(stdout)
 }
 
+/// Describes how a `--dry-run` would change a file, as a unified diff.
+pub fn get_diff(path: &Path, old: &str, new: &str) -> String {
+    let path = path.display().to_string();
+    let diff = TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(2)
+        .header(&path, &path)
+        .to_string();
+
+    format!("would change {}\n{}", path, diff)
+}
+
+/// Time since the unix epoch, zero for a clock set before it.
+pub fn since_epoch() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// Whether `digest` is a SHA-256 digest in hex, which also makes it safe to use as a file name.
+pub fn is_sha256(digest: &str) -> bool {
+    digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+pub fn sha256_of_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
 pub async fn get_minikube_client() -> Result<kube::Client> {
     let client = match kube::Client::try_default().await {
         Ok(inner) => inner,
         Err(err) => {
-            return Err(anyhow!(
-                "failed to load cluster config: {}",
-                err.to_string()
-            ));
+            return Err(anyhow!("failed to load cluster config: {}", err));
         }
     };
 
@@ -58,7 +116,7 @@ pub async fn get_minikube_client() -> Result<kube::Client> {
         }
     };
 
-    let kubeconfig = config::Kubeconfig::read()?;
+    let kubeconfig = Kubeconfig::read()?;
     if kubeconfig.current_context != Some("minikube".to_string()) {
         return Err(anyhow!(
             "not connected to minikube, current context is {}",
@@ -70,6 +128,8 @@ pub async fn get_minikube_client() -> Result<kube::Client> {
 }
 
 pub fn update_etc_hosts(data: String) -> Result<()> {
+    back_up(&get_etc_hosts_path()?)?;
+
     let tmp_file = Builder::new().tempfile()?;
     fs::write(&tmp_file, data)?;
 
@@ -83,7 +143,7 @@ pub fn update_etc_hosts(data: String) -> Result<()> {
 }
 
 fn copy_as_admin_unix(from: &PathBuf, to: &PathBuf) -> Result<()> {
-    let output = Command::new("sudo").arg("-p").arg("[sudo] ").arg("mv").arg(from).arg(to).output()?;
+    let output = admin_command("mv")?.arg(from).arg(to).output()?;
 
     handle_output(output)?;
 
@@ -135,7 +195,49 @@ fn copy_as_admin_windows(from: &Path, to: &Path) -> Result<()> {
     Ok(())
 }
 
-pub fn should_continue_as_admin() -> Result<bool> {
+/// How to handle the steps that need admin rights.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdminMode {
+    /// Ask on the terminal.
+    Ask,
+    /// Continue without asking, e.g. in provisioning scripts.
+    Yes,
+    /// Skip those steps without asking.
+    No,
+}
+
+impl AdminMode {
+    /// `--no-admin` wins over `--yes`, which can also be given as `KRUNCH_ASSUME_YES=1`.
+    pub fn from_flags(yes: bool, no_admin: bool) -> AdminMode {
+        AdminMode::from_flags_and_env(yes, no_admin, env::var(ASSUME_YES_VARIABLE).ok())
+    }
+
+    fn from_flags_and_env(yes: bool, no_admin: bool, assume_yes: Option<String>) -> AdminMode {
+        let assume_yes = assume_yes
+            .is_some_and(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"));
+
+        if no_admin {
+            AdminMode::No
+        } else if yes || assume_yes {
+            AdminMode::Yes
+        } else {
+            AdminMode::Ask
+        }
+    }
+}
+
+pub fn should_continue_as_admin(mode: AdminMode) -> Result<bool> {
+    match mode {
+        AdminMode::No => return Ok(false),
+        AdminMode::Yes => {
+            acquire_admin()?;
+            return Ok(true);
+        }
+        AdminMode::Ask => (),
+    }
+
+    check_can_ask(stdin().is_terminal())?;
+
     let mut input = String::new();
 
     loop {
@@ -149,13 +251,7 @@ pub fn should_continue_as_admin() -> Result<bool> {
 
         match input.trim() {
             "y" | "Y" => {
-                if cfg!(target_family = "unix") {
-                    let output = Command::new("sudo").arg("-k").output()?;
-                    handle_output(output)?;
-
-                    let output = Command::new("sudo").arg("true").output()?;
-                    handle_output(output)?;
-                }
+                acquire_admin()?;
                 return Ok(true);
             }
             "n" | "N" | "" => return Ok(false),
@@ -166,3 +262,109 @@ pub fn should_continue_as_admin() -> Result<bool> {
         }
     }
 }
+
+/// Without a terminal nobody can answer, so failing beats waiting forever.
+fn check_can_ask(is_terminal: bool) -> Result<()> {
+    if is_terminal {
+        return Ok(());
+    }
+
+    Err(anyhow!(
+        "modifying etc/hosts and the root store requires admin rights, but there is no \
+        terminal to ask for them: pass --yes (or set {}=1) to continue or --no-admin to skip \
+        these steps",
+        ASSUME_YES_VARIABLE
+    ))
+}
+
+/// Makes sure sudo works for the following steps, so that its password is asked for once and
+/// up front. Not needed when krunch already runs as root, and on Windows, where each step
+/// asks through UAC.
+fn acquire_admin() -> Result<()> {
+    if !cfg!(target_family = "unix") || is_root()? {
+        return Ok(());
+    }
+
+    if env::var_os("SUDO_ASKPASS").is_some() {
+        let output = Command::new("sudo").arg("-A").arg("true").output()?;
+        handle_output(output)?;
+    } else if stdin().is_terminal() {
+        let output = Command::new("sudo").arg("-k").output()?;
+        handle_output(output)?;
+
+        let output = Command::new("sudo").arg("true").output()?;
+        handle_output(output)?;
+    } else {
+        // without a terminal, only cached credentials or passwordless sudo can work
+        let output = Command::new("sudo").arg("-n").arg("true").output()?;
+        handle_output(output).map_err(|err| {
+            anyhow!(
+                "sudo needs a password, but there is no terminal to ask for it ({}): set \
+                SUDO_ASKPASS, allow passwordless sudo or run krunch as root",
+                err
+            )
+        })?;
+    }
+
+    Ok(())
+}
+
+fn is_root() -> Result<bool> {
+    let output = Command::new("id").arg("-u").output()?;
+    Ok(handle_output(output)? == "0")
+}
+
+/// Runs a command with admin rights, through sudo unless krunch already runs as root.
+fn admin_command(program: &str) -> Result<Command> {
+    if is_root()? {
+        return Ok(Command::new(program));
+    }
+
+    let mut command = Command::new("sudo");
+    if env::var_os("SUDO_ASKPASS").is_some() {
+        command.arg("-A");
+    } else {
+        command.arg("-p").arg("[sudo] ");
+    }
+    command.arg(program);
+
+    Ok(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_admin_mode_from_flags() {
+        let mode = |yes, no_admin, env: Option<&str>| {
+            AdminMode::from_flags_and_env(yes, no_admin, env.map(str::to_string))
+        };
+
+        assert_eq!(mode(false, false, None), AdminMode::Ask);
+        assert_eq!(mode(true, false, None), AdminMode::Yes);
+        assert_eq!(mode(false, true, None), AdminMode::No);
+        // --no-admin wins over --yes and the variable
+        assert_eq!(mode(true, true, None), AdminMode::No);
+        assert_eq!(mode(false, true, Some("1")), AdminMode::No);
+
+        for value in ["1", "true", "TRUE", "yes", "Yes"] {
+            assert_eq!(mode(false, false, Some(value)), AdminMode::Yes, "{}", value);
+        }
+        for value in ["", "0", "false", "no", "y"] {
+            assert_eq!(mode(false, false, Some(value)), AdminMode::Ask, "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_check_can_ask() {
+        assert!(check_can_ask(true).is_ok());
+        let err = check_can_ask(false).unwrap_err().to_string();
+        assert!(
+            err.contains("--yes") && err.contains("--no-admin"),
+            "{}",
+            err
+        );
+        assert!(err.contains(ASSUME_YES_VARIABLE));
+    }
+}
@@ -0,0 +1,358 @@
+use crate::shared::config::KrunchConfig;
+use crate::shared::file_folder_paths::get_releases_file_path;
+use crate::shared::http::fetch_text_with_headers;
+use crate::shared::since_epoch;
+use crate::shared::tools::Tool;
+use anyhow::{anyhow, Result};
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION};
+use reqwest::{Client, Url};
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::{env, fmt, fs};
+use tempfile::NamedTempFile;
+
+const GITHUB_PAGE_SIZE: usize = 100;
+const MAX_GITHUB_PAGES: usize = 10;
+
+/// Where the released versions of a tool are listed, needed to resolve ranges and `latest`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ReleaseSource {
+    /// GitHub repository, e.g. `helm/helm`, whose releases are the versions of the tool.
+    pub github: Option<String>,
+    /// Lists the repository's tags instead, for projects that don't publish GitHub releases.
+    #[serde(default)]
+    pub tags: bool,
+    /// Url of a text file with one version per line, like the stable.txt of dl.k8s.io.
+    pub url: Option<String>,
+}
+
+/// A configured tool version.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VersionSpec {
+    Exact(String),
+    Latest,
+    Range(VersionReq),
+}
+
+impl VersionSpec {
+    /// Full versions like `1.28.3` are exact, anything else semver accepts is a range, e.g.
+    /// `~1.28`, `^3.14` or `>=2.0, <3`. Other strings are taken as exact versions. A bare
+    /// `1.28` means `~1.28`, the newest 1.28.x, rather than semver's default of `^1.28`.
+    pub fn parse(spec: &str) -> VersionSpec {
+        if spec == "latest" {
+            return VersionSpec::Latest;
+        }
+        if Version::parse(spec).is_ok() {
+            return VersionSpec::Exact(spec.to_string());
+        }
+
+        let is_partial = !spec.is_empty()
+            && spec
+                .split('.')
+                .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()));
+        if is_partial {
+            if let Ok(req) = VersionReq::parse(&format!("~{}", spec)) {
+                return VersionSpec::Range(req);
+            }
+        }
+
+        match VersionReq::parse(spec) {
+            Ok(req) => VersionSpec::Range(req),
+            Err(_) => VersionSpec::Exact(spec.to_string()),
+        }
+    }
+
+    pub fn matches(&self, version: &str) -> bool {
+        match self {
+            VersionSpec::Exact(exact) => exact == version,
+            VersionSpec::Latest => parse_release(version).is_some(),
+            VersionSpec::Range(req) => parse_release(version).is_some_and(|v| req.matches(&v)),
+        }
+    }
+
+    /// Returns the highest of `versions` the spec accepts.
+    pub fn select<'a>(&self, versions: impl IntoIterator<Item = &'a str>) -> Option<String> {
+        versions
+            .into_iter()
+            .filter(|version| self.matches(version))
+            .max_by_key(|version| release_order(version))
+            .map(|version| version.to_string())
+    }
+}
+
+impl fmt::Display for VersionSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VersionSpec::Exact(version) => write!(f, "{}", version),
+            VersionSpec::Latest => write!(f, "latest"),
+            VersionSpec::Range(req) => write!(f, "{}", req),
+        }
+    }
+}
+
+/// Turns the configured version of a tool into an exact one. Upstream is only asked for ranges
+/// and `latest`.
+pub async fn resolve_release(
+    tool: &Tool,
+    spec: &str,
+    client: &Client,
+    config: &KrunchConfig,
+) -> Result<String> {
+    let spec = VersionSpec::parse(spec);
+    if let VersionSpec::Exact(version) = spec {
+        return Ok(version);
+    }
+
+    let releases = get_releases(tool, client, config).await?;
+    spec.select(releases.iter().map(String::as_str))
+        .ok_or(anyhow!("no release of {} matches '{}'", tool.name, spec))
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ReleaseCache {
+    tools: BTreeMap<String, CachedReleases>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedReleases {
+    /// The source the versions were listed from, the entry is stale once it changes.
+    source: String,
+    /// Seconds since the unix epoch.
+    fetched: u64,
+    versions: Vec<String>,
+}
+
+/// Returns the stable released versions of a tool, newest first. The lists are cached for
+/// `releases.cache_hours`.
+pub async fn get_releases(
+    tool: &Tool,
+    client: &Client,
+    config: &KrunchConfig,
+) -> Result<Vec<String>> {
+    let source = tool.releases.as_ref().ok_or(anyhow!(
+        "{} has no release source, configure an exact version or add `releases` to its definition",
+        tool.name
+    ))?;
+    let source_key = serde_json::to_string(source)?;
+    let mut cache = read_cache()?;
+
+    if let Some(cached) = cache.tools.get(&tool.name) {
+        let max_age = config.releases.cache_hours * 3600;
+        if cached.source == source_key
+            && since_epoch().as_secs().saturating_sub(cached.fetched) < max_age
+        {
+            return Ok(cached.versions.clone());
+        }
+    }
+
+    let versions = fetch_releases(source, client, config)
+        .await
+        .map_err(|err| anyhow!("failed to list the releases of {}: {}", tool.name, err))?;
+
+    cache.tools.insert(
+        tool.name.clone(),
+        CachedReleases {
+            source: source_key,
+            fetched: since_epoch().as_secs(),
+            versions: versions.clone(),
+        },
+    );
+    write_cache(&cache)?;
+
+    Ok(versions)
+}
+
+async fn fetch_releases(
+    source: &ReleaseSource,
+    client: &Client,
+    config: &KrunchConfig,
+) -> Result<Vec<String>> {
+    let mut names = vec![];
+    if let Some(repo) = &source.github {
+        names.extend(fetch_github(repo, source.tags, client, config).await?);
+    }
+    if let Some(url) = &source.url {
+        let url = Url::parse(url)?;
+        let text =
+            fetch_text_with_headers(client, &config.download, &url, HeaderMap::new()).await?;
+        names.extend(text.lines().map(|line| line.to_string()));
+    }
+
+    let mut versions: Vec<Version> = names
+        .iter()
+        .filter_map(|name| parse_release(name))
+        .collect();
+    versions.sort_by(|a, b| b.cmp(a));
+    versions.dedup();
+
+    Ok(versions.iter().map(Version::to_string).collect())
+}
+
+#[derive(Deserialize)]
+struct GithubRelease {
+    tag_name: String,
+    #[serde(default)]
+    draft: bool,
+    #[serde(default)]
+    prerelease: bool,
+}
+
+#[derive(Deserialize)]
+struct GithubTag {
+    name: String,
+}
+
+async fn fetch_github(
+    repo: &str,
+    tags: bool,
+    client: &Client,
+    config: &KrunchConfig,
+) -> Result<Vec<String>> {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        ACCEPT,
+        HeaderValue::from_static("application/vnd.github+json"),
+    );
+    if let Ok(token) = env::var("GITHUB_TOKEN") {
+        headers.insert(AUTHORIZATION, format!("Bearer {}", token).parse()?);
+    }
+
+    let kind = if tags { "tags" } else { "releases" };
+    let mut names = vec![];
+
+    for page in 1..=MAX_GITHUB_PAGES {
+        let url = Url::parse(&format!(
+            "{}/repos/{}/{}?per_page={}&page={}",
+            config.releases.github_api.trim_end_matches('/'),
+            repo,
+            kind,
+            GITHUB_PAGE_SIZE,
+            page
+        ))?;
+        let text = fetch_text_with_headers(client, &config.download, &url, headers.clone()).await?;
+        let parse_error = |err| anyhow!("unexpected response from {}: {}", url, err);
+
+        let count = if tags {
+            let tags: Vec<GithubTag> = serde_json::from_str(&text).map_err(parse_error)?;
+            let count = tags.len();
+            names.extend(tags.into_iter().map(|tag| tag.name));
+            count
+        } else {
+            let releases: Vec<GithubRelease> = serde_json::from_str(&text).map_err(parse_error)?;
+            let count = releases.len();
+            names.extend(
+                releases
+                    .into_iter()
+                    .filter(|release| !release.draft && !release.prerelease)
+                    .map(|release| release.tag_name),
+            );
+            count
+        };
+
+        if count < GITHUB_PAGE_SIZE {
+            break;
+        }
+    }
+
+    Ok(names)
+}
+
+/// Sort key that orders versions by semver, with anything that isn't a stable release, e.g. a
+/// pre-release or a folder named `1.28`, below all releases.
+pub fn release_order(version: &str) -> (Option<Version>, &str) {
+    (parse_release(version), version)
+}
+
+/// Parses a stable version, with or without a leading `v`.
+fn parse_release(name: &str) -> Option<Version> {
+    let name = name.trim();
+    let version = Version::parse(name.strip_prefix('v').unwrap_or(name)).ok()?;
+    version.pre.is_empty().then_some(version)
+}
+
+fn read_cache() -> Result<ReleaseCache> {
+    let path = get_releases_file_path()?;
+    if !path.exists() {
+        return Ok(ReleaseCache::default());
+    }
+
+    // a broken cache only costs a lookup, so it is not worth failing over
+    Ok(serde_json::from_str(&fs::read_to_string(path)?).unwrap_or_default())
+}
+
+fn write_cache(cache: &ReleaseCache) -> Result<()> {
+    let path = get_releases_file_path()?;
+    let folder = path
+        .parent()
+        .ok_or(anyhow!("invalid release cache path {}", path.display()))?;
+    fs::create_dir_all(folder)?;
+
+    let tmp_file = NamedTempFile::new_in(folder)?;
+    serde_json::to_writer_pretty(&tmp_file, cache)?;
+    tmp_file.persist(path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::http::get_http_client;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_resolve_from_local_stand_in() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/repos/helm/helm/releases"))
+            .and(query_param("page", "1"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"[
+                    { "tag_name": "v3.15.0-rc.1", "prerelease": true },
+                    { "tag_name": "v3.14.4" },
+                    { "tag_name": "v3.13.3" },
+                    { "tag_name": "v2.17.0" }
+                ]"#,
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/stable.txt"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("v3.14.2\n"))
+            .mount(&server)
+            .await;
+
+        let mut config = KrunchConfig::default();
+        config.releases.github_api = server.uri();
+        let client = get_http_client(&config.download).unwrap();
+        let source = ReleaseSource {
+            github: Some("helm/helm".to_string()),
+            tags: false,
+            url: Some(format!("{}/stable.txt", server.uri())),
+        };
+
+        let releases = fetch_releases(&source, &client, &config).await.unwrap();
+        assert_eq!(releases, ["3.14.4", "3.14.2", "3.13.3", "2.17.0"]);
+
+        let select =
+            |spec: &str| VersionSpec::parse(spec).select(releases.iter().map(String::as_str));
+        assert_eq!(select("latest").unwrap(), "3.14.4");
+        assert_eq!(select("~3.13").unwrap(), "3.13.3");
+        // a bare major.minor stays within that minor version
+        assert_eq!(select("3.13").unwrap(), "3.13.3");
+        assert_eq!(select("3").unwrap(), "3.14.4");
+        assert_eq!(select("^2").unwrap(), "2.17.0");
+        assert_eq!(select("3.14.2").unwrap(), "3.14.2");
+        assert!(select("^4").is_none());
+    }
+
+    #[test]
+    fn test_release_order() {
+        assert!(release_order("1.28.3") > release_order("1.9.10"));
+        assert!(release_order("v1.28.3") > release_order("1.28.2"));
+        assert!(release_order("1.28") < release_order("1.0.0"));
+        assert!(release_order("1.29.0-rc.1") < release_order("1.28.3"));
+    }
+}
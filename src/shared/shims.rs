@@ -0,0 +1,195 @@
+use crate::shared::config::get_config;
+use crate::shared::releases::VersionSpec;
+use crate::shared::tools::{get_builtin_tools, get_bundled_tools, get_tools, Platform, Tool};
+use anyhow::{anyhow, Result};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Returns the tool krunch stands in for when it was started through one of its shims. With a
+/// broken config, the built-in tools still work. Tools installed from a bundle are found by the
+/// definition saved with them, unless the config defines them as well.
+pub fn get_shim_tool() -> Result<Option<Tool>> {
+    let arg0 = match env::args_os().next() {
+        Some(arg0) => PathBuf::from(arg0),
+        None => return Ok(None),
+    };
+    if arg0
+        .file_stem()
+        .is_none_or(|stem| stem.eq_ignore_ascii_case("krunch"))
+    {
+        return Ok(None);
+    }
+
+    let mut tools = get_bundled_tools().unwrap_or_default();
+    tools.extend(get_tools().or_else(|_| get_builtin_tools())?);
+    Ok(find_shim_tool(&arg0, tools.into_values()))
+}
+
+/// Finds the tool by the stem of the name krunch was started with, since on Windows that is
+/// whatever was typed, usually without `.exe`.
+fn find_shim_tool(arg0: &Path, mut tools: impl Iterator<Item = Tool>) -> Option<Tool> {
+    let stem = arg0.file_stem()?.to_string_lossy();
+    tools.find(|tool| {
+        if cfg!(target_family = "windows") {
+            tool.file_stem().eq_ignore_ascii_case(&stem)
+        } else {
+            tool.file_stem() == stem
+        }
+    })
+}
+
+/// Runs the version of `tool` that applies to the current directory with the shim's arguments
+/// and returns its exit code.
+pub fn run_shim(tool: &Tool) -> Result<i32> {
+    // a mistake in the config must not take every tool down with it
+    let configured = match get_config() {
+        Ok(mut config) => config.versions.remove(&tool.name),
+        Err(err) => {
+            eprintln!("krunch: ignoring the config, {}", err);
+            None
+        }
+    };
+    let version = select_version(tool, configured)?;
+    let path = tool.version_path(&version, &Platform::current()?)?;
+    if !path.exists() {
+        return Err(anyhow!(
+            "{} {} is not installed, run `krunch install` to install it",
+            tool.name,
+            version
+        ));
+    }
+
+    let mut command = Command::new(&path);
+    command.args(env::args_os().skip(1));
+
+    #[cfg(target_family = "unix")]
+    {
+        use std::os::unix::process::CommandExt;
+        // exec only returns if the tool could not be started
+        let err = command.exec();
+        Err(anyhow!("failed to run {}: {}", path.display(), err))
+    }
+
+    #[cfg(not(target_family = "unix"))]
+    {
+        let status = command
+            .status()
+            .map_err(|err| anyhow!("failed to run {}: {}", path.display(), err))?;
+        Ok(status.code().unwrap_or(1))
+    }
+}
+
+/// Picks the version a shim runs: `KRUNCH_<TOOL>_VERSION`, then the version pinned by the nearest
+/// project config or the user config, then the highest installed one. Ranges and `latest` are
+/// matched against the installed versions, shims never ask upstream.
+pub fn resolve_version(tool: &Tool) -> Result<String> {
+    select_version(tool, get_config()?.versions.remove(&tool.name))
+}
+
+fn select_version(tool: &Tool, configured: Option<String>) -> Result<String> {
+    let spec = match env::var(get_version_variable(&tool.name)) {
+        Ok(version) if !version.is_empty() => Some(version),
+        _ => configured,
+    };
+
+    let spec = match spec.map(|spec| VersionSpec::parse(&spec)) {
+        Some(VersionSpec::Exact(version)) => return Ok(version),
+        Some(spec) => spec,
+        None => VersionSpec::Latest,
+    };
+
+    let installed = tool.installed_versions()?;
+    spec.select(installed.iter().map(String::as_str))
+        .ok_or(anyhow!(
+            "no installed version of {} matches '{}', run `krunch install` to install it",
+            tool.name,
+            spec
+        ))
+}
+
+/// Name of the environment variable that overrides the version of a tool, e.g.
+/// `KRUNCH_KUBECTL_VERSION`.
+pub fn get_version_variable(tool_name: &str) -> String {
+    format!(
+        "KRUNCH_{}_VERSION",
+        tool_name.to_uppercase().replace('-', "_")
+    )
+}
+
+/// Puts a shim for `tool` into its folder, which is a link to the krunch executable. Whatever
+/// was installed there before is replaced.
+pub fn create_shim(tool: &Tool, platform: &Platform) -> Result<()> {
+    let folder = tool.folder()?;
+    fs::create_dir_all(&folder)?;
+    let shim = folder.join(tool.file_name(platform));
+    let krunch = env::current_exe()?;
+
+    if fs::read_link(&shim).ok().as_deref() == Some(krunch.as_path()) {
+        return Ok(());
+    }
+    if shim.symlink_metadata().is_ok() {
+        fs::remove_file(&shim)?;
+    }
+
+    link_executable(&krunch, &shim)
+}
+
+/// Whether `tool` has a shim that runs krunch. Hard links and copies, as used on Windows, are
+/// recognized by their size.
+pub fn has_shim(tool: &Tool) -> Result<bool> {
+    let shim = tool.install_path()?;
+    let krunch = env::current_exe()?;
+
+    Ok(match fs::read_link(&shim) {
+        Ok(target) => target == krunch,
+        Err(_) => shim.exists() && fs::metadata(&shim)?.len() == fs::metadata(&krunch)?.len(),
+    })
+}
+
+#[cfg(target_family = "unix")]
+fn link_executable(krunch: &Path, shim: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(krunch, shim)?;
+    Ok(())
+}
+
+#[cfg(not(target_family = "unix"))]
+fn link_executable(krunch: &Path, shim: &Path) -> Result<()> {
+    // symlinks need extra privileges on Windows, a copy works if the hard link does not
+    if fs::hard_link(krunch, shim).is_err() {
+        fs::copy(krunch, shim)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::tools::{read_definitions, write_definition};
+
+    #[test]
+    fn test_find_shim_tool() {
+        let tools = || get_builtin_tools().unwrap().into_values();
+        let name = |arg0: &str| find_shim_tool(Path::new(arg0), tools()).map(|tool| tool.name);
+
+        assert_eq!(name("kubectl").as_deref(), Some("kubectl"));
+        assert_eq!(name("/home/me/.krunch/bin/helm").as_deref(), Some("helm"));
+        // typed on Windows without `.exe`, or started through the file with it
+        assert_eq!(name("kubectl.exe").as_deref(), Some("kubectl"));
+        assert_eq!(name("kubectl-krew"), None);
+    }
+
+    #[test]
+    fn test_find_bundled_shim_tool() {
+        let tools_folder = tempfile::tempdir().unwrap();
+        let mut tool = get_builtin_tools().unwrap().remove("helm").unwrap();
+        tool.name = "blobtool".to_string();
+        tool.file = Some("blob".to_string());
+        write_definition(tools_folder.path(), &tool).unwrap();
+
+        let tools = read_definitions(tools_folder.path()).unwrap();
+        let found = find_shim_tool(Path::new("/home/me/.krunch/bin/blob"), tools.into_values());
+        assert_eq!(found.map(|tool| tool.name).as_deref(), Some("blobtool"));
+    }
+}
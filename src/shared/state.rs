@@ -0,0 +1,389 @@
+use crate::shared::file_folder_paths::get_state_file_path;
+use crate::shared::steps::{get_steps, Outcome};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+/// Everything `krunch install` changed outside the krunch folder, by step. Written to
+/// `~/.krunch/state.json` after each change, so that `krunch remove` undoes only what krunch did.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    #[serde(default)]
+    pub steps: BTreeMap<String, Vec<Change>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Change {
+    /// Lines in krunch's block of a shell profile.
+    ProfileLines {
+        path: PathBuf,
+        lines: Vec<String>,
+    },
+    /// A line in krunch's block of the hosts file.
+    HostsEntry {
+        line: String,
+    },
+    /// A user environment variable set on Windows.
+    UserVariable {
+        name: String,
+        value: String,
+    },
+    /// A folder added to the user's Path on Windows.
+    UserPathEntry {
+        folder: String,
+    },
+    NodeLabel {
+        node: String,
+        key: String,
+    },
+    Addon {
+        name: String,
+    },
+    /// The mkcert CA, added to the system's trust store.
+    LocalCa,
+    /// A secret, identified by its uid so that one created by someone else isn't deleted.
+    Secret {
+        namespace: String,
+        name: String,
+        uid: String,
+    },
+}
+
+impl Change {
+    /// Whether both changes are to the same thing, e.g. the same profile, so that recording one
+    /// replaces the other.
+    pub fn same_target(&self, other: &Change) -> bool {
+        match (self, other) {
+            (Change::ProfileLines { path, .. }, Change::ProfileLines { path: other, .. }) => {
+                path == other
+            }
+            (Change::HostsEntry { .. }, Change::HostsEntry { .. }) => true,
+            (Change::UserVariable { name, .. }, Change::UserVariable { name: other, .. }) => {
+                name == other
+            }
+            (Change::UserPathEntry { folder }, Change::UserPathEntry { folder: other }) => {
+                folder == other
+            }
+            (
+                Change::NodeLabel { node, key },
+                Change::NodeLabel {
+                    node: other_node,
+                    key: other_key,
+                },
+            ) => node == other_node && key == other_key,
+            (Change::Addon { name }, Change::Addon { name: other }) => name == other,
+            (Change::LocalCa, Change::LocalCa) => true,
+            (
+                Change::Secret {
+                    namespace, name, ..
+                },
+                Change::Secret {
+                    namespace: other_namespace,
+                    name: other_name,
+                    ..
+                },
+            ) => namespace == other_namespace && name == other_name,
+            _ => false,
+        }
+    }
+}
+
+/// Describes how the change is undone, e.g. for `krunch remove --dry-run`.
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::ProfileLines { path, lines } => {
+                write!(f, "delete from {}:", path.display())?;
+                for line in lines {
+                    write!(f, "\n    {}", line)?;
+                }
+                Ok(())
+            }
+            Change::HostsEntry { line } => write!(f, "delete `{}` from the hosts file", line),
+            Change::UserVariable { name, .. } => write!(f, "delete the user variable {}", name),
+            Change::UserPathEntry { folder } => write!(f, "delete {} from the user's Path", folder),
+            Change::NodeLabel { node, key } => write!(f, "remove label {} from node {}", key, node),
+            Change::Addon { name } => write!(f, "disable the {} addon", name),
+            Change::LocalCa => write!(f, "remove the mkcert CA from the trust store"),
+            Change::Secret {
+                namespace, name, ..
+            } => write!(f, "delete secret {}/{}", namespace, name),
+        }
+    }
+}
+
+impl State {
+    /// Reads the state file, an install that changed nothing yet has an empty state.
+    pub fn read() -> Result<State> {
+        let path = get_state_file_path()?;
+        if !path.exists() {
+            return Ok(State::default());
+        }
+
+        let contents = fs::read_to_string(&path)?;
+        serde_json::from_str(&contents)
+            .map_err(|err| anyhow!("failed to parse {}: {}", path.display(), err))
+    }
+
+    pub fn write(&self) -> Result<()> {
+        let path = get_state_file_path()?;
+        let folder = path
+            .parent()
+            .ok_or(anyhow!("invalid state file path {}", path.display()))?;
+        fs::create_dir_all(folder)?;
+
+        let mut tmp_file = NamedTempFile::new_in(folder)?;
+        serde_json::to_writer_pretty(&mut tmp_file, self)?;
+        tmp_file.persist(&path)?;
+
+        Ok(())
+    }
+
+    pub fn changes(&self, step: &str) -> Vec<Change> {
+        self.steps.get(step).cloned().unwrap_or_default()
+    }
+
+    pub fn set_changes(&mut self, step: &str, changes: Vec<Change>) {
+        if changes.is_empty() {
+            self.steps.remove(step);
+        } else {
+            self.steps.insert(step.to_string(), changes);
+        }
+    }
+
+    /// Adds a change of `step`, replacing an earlier one to the same target.
+    pub fn record(&mut self, step: &str, change: Change) {
+        let changes = self.steps.entry(step.to_string()).or_default();
+        match changes.iter_mut().find(|other| other.same_target(&change)) {
+            Some(other) => *other = change,
+            None => changes.push(change),
+        }
+    }
+
+    /// The lines of krunch's block in a shell profile, from every step in install order.
+    pub fn profile_lines(&self, profile: &Path) -> Vec<String> {
+        let mut lines = vec![];
+        for step in get_steps() {
+            for change in self.changes(step.name()) {
+                if let Change::ProfileLines { path, lines: added } = change {
+                    if path == profile {
+                        lines.extend(added);
+                    }
+                }
+            }
+        }
+        lines
+    }
+
+    /// The lines of krunch's block in the hosts file, from every step in install order.
+    pub fn hosts_lines(&self) -> Vec<String> {
+        let mut lines = vec![];
+        for step in get_steps() {
+            for change in self.changes(step.name()) {
+                if let Change::HostsEntry { line } = change {
+                    lines.push(line);
+                }
+            }
+        }
+        lines
+    }
+
+    /// Drops the change of `step` to the same target as `change`.
+    pub fn forget(&mut self, step: &str, change: &Change) {
+        if let Some(changes) = self.steps.get_mut(step) {
+            changes.retain(|other| !other.same_target(change));
+            if changes.is_empty() {
+                self.steps.remove(step);
+            }
+        }
+    }
+}
+
+/// Records a change of `step` in the state file right away, so that it's known even if a later
+/// step fails.
+pub fn record_change(step: &str, change: Change) -> Result<()> {
+    let mut state = State::read()?;
+    state.record(step, change);
+    state.write()
+}
+
+/// Collects the result of reverting the recorded changes of a step one by one. Changes that were
+/// modified or undone by someone else since install are left alone and reported.
+pub struct Reversal {
+    step: &'static str,
+    dry_run: bool,
+    /// What a dry run reports for each change it would undo.
+    undone: Vec<String>,
+    drifted: Vec<String>,
+}
+
+impl Reversal {
+    pub fn new(step: &'static str, dry_run: bool) -> Reversal {
+        Reversal {
+            step,
+            dry_run,
+            undone: vec![],
+            drifted: vec![],
+        }
+    }
+
+    /// Notes a change that was undone, or with `drift` why it was left alone. Unless this is a dry
+    /// run, krunch no longer owns the change afterwards.
+    pub fn add(&mut self, change: &Change, drift: Option<String>) -> Result<()> {
+        match drift {
+            Some(drift) => self.drifted.push(drift),
+            None => self.undone.push(format!("would {}", change)),
+        }
+
+        self.forget(change)
+    }
+
+    /// Notes an undone change to a file, which a dry run shows as the `diff` of the file.
+    pub fn add_diff(&mut self, change: &Change, diff: String) -> Result<()> {
+        self.undone.push(diff.trim_end().to_string());
+
+        self.forget(change)
+    }
+
+    fn forget(&self, change: &Change) -> Result<()> {
+        if !self.dry_run {
+            let mut state = State::read()?;
+            state.forget(self.step, change);
+            state.write()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(self) -> Outcome {
+        if self.undone.is_empty() && self.drifted.is_empty() {
+            return Outcome::NothingToDo;
+        }
+
+        let mut message = if self.dry_run && !self.undone.is_empty() {
+            self.undone.join("\n")
+        } else if self.drifted.is_empty() {
+            return Outcome::Done;
+        } else if self.undone.is_empty() {
+            String::new()
+        } else {
+            "success".to_string()
+        };
+
+        if !self.drifted.is_empty() {
+            if !message.is_empty() {
+                message.push('\n');
+            }
+            message.push_str("left alone, changed since install:");
+            for drift in &self.drifted {
+                message.push_str(&format!("\n  {}", drift));
+            }
+        }
+
+        Outcome::Message(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::get_diff;
+
+    #[test]
+    fn test_record_replaces_same_target() {
+        let mut state = State::default();
+        state.record(
+            "dns",
+            Change::HostsEntry {
+                line: "192.168.49.2\tk8s.local".to_string(),
+            },
+        );
+        state.record(
+            "dns",
+            Change::HostsEntry {
+                line: "192.168.49.3\tk8s.local".to_string(),
+            },
+        );
+        state.record(
+            "tls",
+            Change::Secret {
+                namespace: "default".to_string(),
+                name: "tls".to_string(),
+                uid: "1".to_string(),
+            },
+        );
+        state.record("tls", Change::LocalCa);
+
+        assert_eq!(
+            state.changes("dns"),
+            [Change::HostsEntry {
+                line: "192.168.49.3\tk8s.local".to_string()
+            }]
+        );
+        assert_eq!(state.changes("tls").len(), 2);
+
+        state.forget("tls", &Change::LocalCa);
+        state.forget(
+            "dns",
+            &Change::HostsEntry {
+                line: "192.168.49.3\tk8s.local".to_string(),
+            },
+        );
+
+        assert_eq!(state.changes("tls").len(), 1);
+        assert!(!state.steps.contains_key("dns"));
+    }
+
+    #[test]
+    fn test_dry_run_plan() {
+        let hosts_entry = Change::HostsEntry {
+            line: "192.168.49.2\tk8s.local".to_string(),
+        };
+        let diff = get_diff(
+            Path::new("/etc/hosts"),
+            "127.0.0.1 localhost\n192.168.49.2\tk8s.local\n",
+            "127.0.0.1 localhost\n",
+        );
+
+        let mut reversal = Reversal::new("dns", true);
+        reversal.add_diff(&hosts_entry, diff).unwrap();
+        reversal
+            .add(&Change::LocalCa, Some("the CA is gone".to_string()))
+            .unwrap();
+        let Outcome::Message(plan) = reversal.finish() else {
+            panic!("expected a plan");
+        };
+        assert_eq!(
+            plan,
+            "would change /etc/hosts\n\
+             --- /etc/hosts\n\
+             +++ /etc/hosts\n\
+             @@ -1,2 +1 @@\n \
+             127.0.0.1 localhost\n\
+             -192.168.49.2\tk8s.local\n\
+             left alone, changed since install:\n  the CA is gone"
+        );
+
+        let mut reversal = Reversal::new("ingress", true);
+        reversal
+            .add(
+                &Change::Addon {
+                    name: "ingress".to_string(),
+                },
+                None,
+            )
+            .unwrap();
+        assert!(
+            matches!(reversal.finish(), Outcome::Message(plan) if plan == "would disable the ingress addon")
+        );
+        assert!(matches!(
+            Reversal::new("tls", true).finish(),
+            Outcome::NothingToDo
+        ));
+    }
+}
@@ -0,0 +1,656 @@
+use crate::cli_install::bin_folder_to_path::PathStep;
+use crate::cli_install::create_ca_and_tls::TlsStep;
+use crate::cli_install::dns_for_minikube::DnsStep;
+use crate::cli_install::docker_to_minikube::DockerStep;
+use crate::cli_install::download_binaries::ToolsStep;
+use crate::cli_install::enable_ingress::IngressStep;
+use crate::shared::file_folder_paths::get_binary_path;
+use crate::shared::state::{Change, State};
+use crate::shared::tools::Platform;
+use crate::shared::{should_continue_as_admin, AdminMode};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use clap::builder::{PossibleValue, PossibleValuesParser};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::Command;
+use std::sync::OnceLock;
+use tokio::signal;
+
+/// One part of the setup, e.g. the DNS entry. `install`, `remove`, `status` and `doctor` all work
+/// through the steps returned by [`get_steps`], so a new step only has to be added there.
+// not Send, the steps only run on the main task and the parallel downloads can't be proven Send
+#[async_trait(?Send)]
+pub trait Step {
+    /// Name used by `--only` and `--skip`, e.g. `dns`.
+    fn name(&self) -> &'static str;
+
+    /// What `apply` does, printed by install, e.g. "creating DNS entry".
+    fn describe(&self) -> &'static str;
+
+    /// What `revert` does, printed by remove. Steps that remove leaves in place return `None`.
+    fn describe_revert(&self) -> Option<&'static str>;
+
+    fn requires_admin(&self) -> bool {
+        false
+    }
+
+    /// Whether the step talks to minikube, which then has to be running.
+    fn requires_minikube(&self) -> bool {
+        true
+    }
+
+    /// Names of the steps this one builds on, e.g. the CA needs the mkcert tool. A step is
+    /// skipped when a step it depends on was skipped, and on remove it is kept while a step that
+    /// depends on it could not be removed.
+    fn dependencies(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Reports the current state without changing anything or asking for admin rights.
+    async fn check(&self) -> Vec<Check>;
+
+    async fn apply(&self, options: &StepOptions) -> Result<Outcome>;
+
+    async fn revert(&self, options: &StepOptions) -> Result<Outcome>;
+
+    /// What the step recorded in state.json, see [`State`].
+    fn recorded(&self) -> Result<Vec<Change>> {
+        Ok(State::read()?.changes(self.name()))
+    }
+
+    /// Reverts only `changes` of what the step recorded, the rest of its record stays in place,
+    /// e.g. an entry that existed before and was only updated.
+    async fn revert_changes(&self, changes: &[Change]) -> Result<Outcome> {
+        let mut state = State::read()?;
+        let kept: Vec<Change> = state
+            .changes(self.name())
+            .into_iter()
+            .filter(|change| !changes.contains(change))
+            .collect();
+        state.set_changes(self.name(), changes.to_vec());
+        state.write()?;
+
+        let result = self.revert(&StepOptions::default()).await;
+
+        let mut state = State::read()?;
+        for change in kept {
+            state.record(self.name(), change);
+        }
+        state.write()?;
+
+        result
+    }
+}
+
+/// All steps in the order install runs them, remove reverts them in reverse.
+pub fn get_steps() -> Vec<Box<dyn Step>> {
+    vec![
+        Box::new(ToolsStep),
+        Box::new(PathStep),
+        Box::new(DockerStep),
+        Box::new(IngressStep),
+        Box::new(DnsStep),
+        Box::new(TlsStep),
+    ]
+}
+
+/// Parses step names for `--only` and `--skip`, with each step's description as help.
+pub fn step_name_parser() -> PossibleValuesParser {
+    PossibleValuesParser::new(
+        get_steps()
+            .into_iter()
+            .map(|step| PossibleValue::new(step.name()).help(step.describe())),
+    )
+}
+
+/// Names of the steps in `only`, or of all steps if it's empty, without those in `skip`.
+pub fn select_steps(only: &[String], skip: &[String]) -> Vec<String> {
+    get_steps()
+        .iter()
+        .map(|step| step.name().to_string())
+        .filter(|name| only.is_empty() || only.contains(name))
+        .filter(|name| !skip.contains(name))
+        .collect()
+}
+
+/// Whether any of the selected steps needs a running minikube.
+pub fn requires_minikube(selected: &[String]) -> bool {
+    get_steps()
+        .iter()
+        .any(|step| selected.iter().any(|name| name == step.name()) && step.requires_minikube())
+}
+
+#[derive(Default)]
+pub struct StepOptions<'a> {
+    /// Only describe the changes, see `--dry-run`.
+    pub dry_run: bool,
+    /// Platform to download tools for, the current one if not set.
+    pub platform: Option<Platform>,
+    pub bundle: Option<&'a Path>,
+    pub locked: bool,
+}
+
+/// What applying or reverting a step did.
+pub enum Outcome {
+    Done,
+    AlreadyDone,
+    NothingToDo,
+    /// Anything else, e.g. a dry run's plan.
+    Message(String),
+    /// The step already printed its own report, e.g. a line per download.
+    Reported,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Done => write!(f, "success"),
+            Outcome::AlreadyDone => write!(f, "already done"),
+            Outcome::NothingToDo => write!(f, "nothing to do"),
+            Outcome::Message(message) => write!(f, "{}", message.trim_end()),
+            Outcome::Reported => Ok(()),
+        }
+    }
+}
+
+/// The state of one aspect of a step, a step may report several.
+pub struct Check {
+    pub label: String,
+    pub status: CheckStatus,
+}
+
+pub enum CheckStatus {
+    /// In place, with a short state for `krunch status`, e.g. "enabled".
+    Ok(String),
+    Problem {
+        description: String,
+        /// What to do about it, shown by `krunch doctor`.
+        remedy: String,
+        /// Whether applying the step again repairs it.
+        fixable: bool,
+    },
+    /// The check can't run because something it needs is missing, e.g. minikube.
+    Skipped(String),
+    /// The check itself failed.
+    Error(anyhow::Error),
+}
+
+impl Check {
+    pub fn new(label: &str, status: CheckStatus) -> Check {
+        Check {
+            label: label.to_string(),
+            status,
+        }
+    }
+
+    pub fn ok(label: &str, state: &str) -> Check {
+        Check::new(label, CheckStatus::Ok(state.to_string()))
+    }
+
+    pub fn problem(label: &str, description: &str, remedy: &str, fixable: bool) -> Check {
+        Check::new(
+            label,
+            CheckStatus::Problem {
+                description: description.to_string(),
+                remedy: remedy.to_string(),
+                fixable,
+            },
+        )
+    }
+
+    /// A check that could not run, skipped if minikube isn't available or failed otherwise.
+    pub fn unavailable(label: &str, err: anyhow::Error) -> Check {
+        Check::unavailable_with(label, err, get_minikube_state())
+    }
+
+    fn unavailable_with(label: &str, err: anyhow::Error, minikube: Result<(), String>) -> Check {
+        match minikube {
+            Err(reason) => Check::new(label, CheckStatus::Skipped(reason)),
+            Ok(()) => Check::new(label, CheckStatus::Error(err)),
+        }
+    }
+}
+
+/// Fails with the reason when minikube is not installed or not running. Checked once per run.
+pub fn get_minikube_state() -> Result<(), String> {
+    static STATE: OnceLock<Result<(), String>> = OnceLock::new();
+
+    STATE
+        .get_or_init(|| {
+            let minikube = get_binary_path("minikube").map_err(|err| err.to_string())?;
+            check_minikube(Command::new(minikube))
+        })
+        .clone()
+}
+
+/// Runs `minikube status`. krunch doesn't manage minikube, so it is looked up on the PATH and
+/// only counts as missing if it can't be started.
+fn check_minikube(mut command: Command) -> Result<(), String> {
+    match command.arg("status").output() {
+        Ok(output) if output.status.success() => Ok(()),
+        Ok(_) => Err("minikube is not running".to_string()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            Err("minikube is not installed".to_string())
+        }
+        Err(err) => Err(format!("failed to run minikube: {}", err)),
+    }
+}
+
+/// Applies the selected steps in order and prints the outcome of each. Stops at the first step
+/// that fails or when interrupted with Ctrl-C and then, with `rollback`, reverts the steps that
+/// already changed something in reverse order.
+pub async fn apply_steps(
+    selected: &[String],
+    options: &StepOptions<'_>,
+    admin: AdminMode,
+    rollback: bool,
+) -> Result<()> {
+    let steps = get_steps();
+    let as_admin = ask_for_admin(&steps, selected, options.dry_run, admin)?;
+
+    run_steps(&steps, selected, options, as_admin, rollback).await
+}
+
+async fn run_steps(
+    steps: &[Box<dyn Step>],
+    selected: &[String],
+    options: &StepOptions<'_>,
+    as_admin: bool,
+    rollback: bool,
+) -> Result<()> {
+    let mut skipped = BTreeSet::new();
+    let mut journal = Journal::default();
+    let interrupted = signal::ctrl_c();
+    tokio::pin!(interrupted);
+
+    for step in steps {
+        let label = step.describe();
+        let reason = if !selected.iter().any(|name| name == step.name()) {
+            Some("not selected".to_string())
+        } else if step.requires_admin() && !as_admin {
+            Some("not admin".to_string())
+        } else {
+            step.dependencies()
+                .iter()
+                .find(|dependency| skipped.contains(*dependency))
+                .map(|dependency| format!("needs {}", dependency))
+        };
+
+        if let Some(reason) = reason {
+            // steps that weren't selected may well be in place already
+            if reason != "not selected" {
+                skipped.insert(step.name());
+            }
+            println!("{:<35}skipped ({})", label, reason);
+            continue;
+        }
+
+        print!("{:<35}", label);
+        io::stdout().flush().unwrap();
+        let before = if options.dry_run {
+            vec![]
+        } else {
+            step.recorded().unwrap_or_default()
+        };
+        let result = tokio::select! {
+            result = step.apply(options) => result,
+            _ = &mut interrupted => Err(anyhow!("interrupted")),
+        };
+        // a failed or interrupted step may have recorded changes as well
+        if !options.dry_run {
+            journal.record(step.as_ref(), &before, step.recorded().unwrap_or_default());
+        }
+
+        match result {
+            Ok(outcome) => report(outcome),
+            Err(err) => {
+                println!("failed");
+                if rollback {
+                    journal.roll_back().await;
+                } else if !journal.is_empty() {
+                    println!("\nleaving the steps applied so far in place (--no-rollback)");
+                }
+                return Err(err);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// What each step of an install recorded in state.json so far, so that a failure doesn't leave
+/// the machine half-configured. Steps that record nothing, like the downloads, are kept.
+#[derive(Default)]
+struct Journal<'a> {
+    entries: Vec<JournalEntry<'a>>,
+}
+
+struct JournalEntry<'a> {
+    step: &'a dyn Step,
+    /// Changes to targets the step didn't own before this install.
+    added: Vec<Change>,
+    /// Whether the step only updated something it already owned, e.g. the hosts entry after a
+    /// minikube restart. Reverting that would undo the earlier install, so it's kept.
+    updated: bool,
+}
+
+impl<'a> Journal<'a> {
+    fn record(&mut self, step: &'a dyn Step, before: &[Change], after: Vec<Change>) {
+        let (updated, added): (Vec<Change>, Vec<Change>) = after
+            .into_iter()
+            .filter(|change| !before.contains(change))
+            .partition(|change| before.iter().any(|other| other.same_target(change)));
+
+        if !added.is_empty() || !updated.is_empty() {
+            self.entries.push(JournalEntry {
+                step,
+                added,
+                updated: !updated.is_empty(),
+            });
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Reverts the added changes, latest step first. Failures are reported and the remaining
+    /// steps are still reverted.
+    async fn roll_back(self) {
+        if self.is_empty() {
+            return;
+        }
+
+        println!("\nrolling back");
+        for entry in self.entries.into_iter().rev() {
+            let step = entry.step;
+            let label = match step.describe_revert() {
+                Some(label) if !entry.added.is_empty() => label,
+                Some(_) if entry.updated => {
+                    println!("{:<35}kept (only updated)", step.describe());
+                    continue;
+                }
+                _ => {
+                    println!("{:<35}kept (can't be reverted)", step.describe());
+                    continue;
+                }
+            };
+
+            print!("{:<35}", label);
+            io::stdout().flush().unwrap();
+            match step.revert_changes(&entry.added).await {
+                Ok(outcome) => report(outcome),
+                Err(err) => println!("{}", err),
+            }
+        }
+    }
+}
+
+/// Reverts the selected steps in reverse order and prints the outcome of each. Failures are
+/// reported and the remaining steps are still reverted.
+pub async fn revert_steps(selected: &[String], dry_run: bool, admin: AdminMode) -> Result<()> {
+    let steps = get_steps();
+    let as_admin = ask_for_admin(&steps, selected, dry_run, admin)?;
+    let options = StepOptions {
+        dry_run,
+        ..Default::default()
+    };
+    let mut kept = BTreeSet::new();
+
+    for step in steps.iter().rev() {
+        let label = match step.describe_revert() {
+            Some(label) => label,
+            None => continue,
+        };
+        if !selected.iter().any(|name| name == step.name()) {
+            continue;
+        }
+
+        let needed = steps.iter().any(|other| {
+            kept.contains(other.name()) && other.dependencies().contains(&step.name())
+        });
+        if step.requires_admin() && !as_admin {
+            kept.insert(step.name());
+            println!("{:<35}skipped (not admin)", label);
+            continue;
+        }
+        if needed {
+            kept.insert(step.name());
+            println!("{:<35}skipped (needed for previously skipped step)", label);
+            continue;
+        }
+
+        print!("{:<35}", label);
+        io::stdout().flush().unwrap();
+        match step.revert(&options).await {
+            Ok(outcome) => report(outcome),
+            Err(err) => {
+                kept.insert(step.name());
+                println!("{}", err)
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Asks once, up front, if any selected step needs admin rights. A dry run changes nothing, so
+/// it doesn't need them to show these steps either.
+fn ask_for_admin(
+    steps: &[Box<dyn Step>],
+    selected: &[String],
+    dry_run: bool,
+    admin: AdminMode,
+) -> Result<bool> {
+    let needed = steps
+        .iter()
+        .any(|step| step.requires_admin() && selected.iter().any(|name| name == step.name()));
+
+    if !needed {
+        return Ok(false);
+    }
+    if dry_run {
+        return Ok(true);
+    }
+
+    should_continue_as_admin(admin)
+}
+
+fn report(outcome: Outcome) {
+    if !matches!(outcome, Outcome::Reported) {
+        println!("{}", outcome);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::fs;
+    use std::rc::Rc;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_minikube() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let minikube = |path: &Path| {
+            let mut command = Command::new("minikube");
+            command.env("PATH", path);
+            command
+        };
+
+        assert_eq!(
+            check_minikube(minikube(dir.path())),
+            Err("minikube is not installed".to_string())
+        );
+
+        let script = dir.path().join("minikube");
+        fs::write(&script, "#!/bin/sh\n[ \"$1\" = status ]\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(check_minikube(minikube(dir.path())), Ok(()));
+
+        fs::write(&script, "#!/bin/sh\necho Stopped\nexit 7\n").unwrap();
+        assert_eq!(
+            check_minikube(minikube(dir.path())),
+            Err("minikube is not running".to_string())
+        );
+    }
+
+    #[test]
+    fn test_check_unavailable() {
+        // with minikube running, the check's own error is what status and doctor show
+        let check = Check::unavailable_with("ingress", anyhow!("connection refused"), Ok(()));
+        assert!(
+            matches!(&check.status, CheckStatus::Error(err) if err.to_string() == "connection refused")
+        );
+
+        let check = Check::unavailable_with(
+            "ingress",
+            anyhow!("connection refused"),
+            Err("minikube is not running".to_string()),
+        );
+        assert!(
+            matches!(&check.status, CheckStatus::Skipped(reason) if reason == "minikube is not running")
+        );
+    }
+
+    #[test]
+    fn test_select_steps() {
+        let names =
+            |names: &[&str]| -> Vec<String> { names.iter().map(|name| name.to_string()).collect() };
+
+        assert_eq!(select_steps(&[], &[]).len(), get_steps().len());
+        assert_eq!(
+            select_steps(&names(&["dns", "tools"]), &[]),
+            names(&["tools", "dns"])
+        );
+        assert_eq!(
+            select_steps(&[], &names(&["tls"])),
+            names(&["tools", "path", "docker", "ingress", "dns"])
+        );
+    }
+
+    /// Logs its calls, records `change` in `state` when applied, and then fails when `fails` is
+    /// set.
+    struct FakeStep {
+        name: &'static str,
+        change: Option<Change>,
+        fails: bool,
+        state: Rc<RefCell<State>>,
+        log: Rc<RefCell<Vec<String>>>,
+    }
+
+    #[async_trait(?Send)]
+    impl Step for FakeStep {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn describe(&self) -> &'static str {
+            self.name
+        }
+
+        fn describe_revert(&self) -> Option<&'static str> {
+            Some(self.name)
+        }
+
+        async fn check(&self) -> Vec<Check> {
+            vec![]
+        }
+
+        async fn apply(&self, _options: &StepOptions) -> Result<Outcome> {
+            self.log.borrow_mut().push(format!("apply {}", self.name));
+            if let Some(change) = &self.change {
+                self.state.borrow_mut().record(self.name, change.clone());
+            }
+            if self.fails {
+                return Err(anyhow!("{} failed", self.name));
+            }
+            Ok(Outcome::Done)
+        }
+
+        async fn revert(&self, _options: &StepOptions) -> Result<Outcome> {
+            unreachable!("a rollback only reverts the changes of this install")
+        }
+
+        fn recorded(&self) -> Result<Vec<Change>> {
+            Ok(self.state.borrow().changes(self.name))
+        }
+
+        async fn revert_changes(&self, changes: &[Change]) -> Result<Outcome> {
+            for change in changes {
+                self.log
+                    .borrow_mut()
+                    .push(format!("revert {} {}", self.name, change));
+                self.state.borrow_mut().forget(self.name, change);
+            }
+            Ok(Outcome::Done)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_roll_back_on_failure() {
+        let log = Rc::new(RefCell::new(vec![]));
+        let state = Rc::new(RefCell::new(State::default()));
+        let hosts_entry = |ip: &str| Change::HostsEntry {
+            line: format!("{}\tk8s.local", ip),
+        };
+        let addon = Change::Addon {
+            name: "ingress".to_string(),
+        };
+        // the hosts entry of an earlier install, which this one only updates
+        state
+            .borrow_mut()
+            .record("dns", hosts_entry("192.168.49.2"));
+
+        let step = |name, change, fails| -> Box<dyn Step> {
+            Box::new(FakeStep {
+                name,
+                change,
+                fails,
+                state: state.clone(),
+                log: log.clone(),
+            })
+        };
+        let steps = [
+            step("tools", None, false),
+            step("ingress", Some(addon.clone()), false),
+            step("dns", Some(hosts_entry("192.168.49.3")), false),
+            step("tls", Some(Change::LocalCa), true),
+        ];
+        let selected: Vec<String> = ["tools", "ingress", "dns", "tls"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+
+        let result = run_steps(&steps, &selected, &StepOptions::default(), false, true).await;
+
+        assert!(result.is_err());
+        // the failed step is reverted from what it recorded, the updated hosts entry and the
+        // tools, which record nothing, are kept
+        assert_eq!(
+            *log.borrow(),
+            [
+                "apply tools",
+                "apply ingress",
+                "apply dns",
+                "apply tls",
+                &format!("revert tls {}", Change::LocalCa),
+                &format!("revert ingress {}", addon),
+            ]
+        );
+        assert_eq!(state.borrow().changes("dns"), [hosts_entry("192.168.49.3")]);
+        assert!(state.borrow().changes("ingress").is_empty());
+
+        log.borrow_mut().clear();
+        let result = run_steps(&steps, &selected, &StepOptions::default(), false, false).await;
+
+        assert!(result.is_err());
+        assert!(!log.borrow().iter().any(|call| call.starts_with("revert")));
+    }
+}
@@ -0,0 +1,544 @@
+use crate::shared::config::get_config;
+use crate::shared::file_folder_paths::{get_bin_folder, get_buildx_folder, get_tools_folder};
+use crate::shared::releases::{release_order, ReleaseSource};
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::str::FromStr;
+use std::time::Duration;
+use std::{env, fmt};
+use tokio::process::Command;
+use tokio::time::timeout;
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+const BUILTIN_TOOLS: &str = include_str!("tools.json");
+
+/// Definition of a tool installed from a bundle, next to its versions in `tools/<tool>`.
+const DEFINITION_FILE: &str = "tool.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetOs {
+    Windows,
+    MacOs,
+    Linux,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetArch {
+    Amd64,
+    Arm64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Platform {
+    pub os: TargetOs,
+    pub arch: TargetArch,
+}
+
+impl Platform {
+    /// Detects the platform krunch is running on.
+    pub fn current() -> Result<Platform> {
+        format!("{}/{}", env::consts::OS, env::consts::ARCH)
+            .parse()
+            .map_err(|err| anyhow!("{}, use --platform to choose one explicitly", err))
+    }
+
+    /// Every platform krunch can download tools for.
+    pub fn all() -> Vec<Platform> {
+        let mut platforms = vec![];
+        for os in [TargetOs::Windows, TargetOs::MacOs, TargetOs::Linux] {
+            for arch in [TargetArch::Amd64, TargetArch::Arm64] {
+                platforms.push(Platform { os, arch });
+            }
+        }
+        platforms
+    }
+
+    pub fn exe_suffix(&self) -> &'static str {
+        match self.os {
+            TargetOs::Windows => ".exe",
+            TargetOs::MacOs | TargetOs::Linux => "",
+        }
+    }
+}
+
+impl FromStr for Platform {
+    type Err = anyhow::Error;
+
+    /// Parses `<os>/<arch>`, accepting both Go style (`darwin/arm64`) and Rust style
+    /// (`macos/aarch64`) names.
+    fn from_str(s: &str) -> Result<Self> {
+        let (os, arch) = s
+            .split_once('/')
+            .ok_or(anyhow!("invalid platform '{}', expected <os>/<arch>", s))?;
+
+        let os = match os {
+            "windows" => TargetOs::Windows,
+            "macos" | "darwin" => TargetOs::MacOs,
+            "linux" => TargetOs::Linux,
+            _ => return Err(anyhow!("platform '{}' is not supported", s)),
+        };
+
+        let arch = match arch {
+            "amd64" | "x86_64" => TargetArch::Amd64,
+            "arm64" | "aarch64" => TargetArch::Arm64,
+            _ => return Err(anyhow!("platform '{}' is not supported", s)),
+        };
+
+        Ok(Platform { os, arch })
+    }
+}
+
+impl TryFrom<String> for Platform {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl From<Platform> for String {
+    fn from(value: Platform) -> Self {
+        value.to_string()
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let os = match self.os {
+            TargetOs::Windows => "windows",
+            TargetOs::MacOs => "darwin",
+            TargetOs::Linux => "linux",
+        };
+        let arch = match self.arch {
+            TargetArch::Amd64 => "amd64",
+            TargetArch::Arm64 => "arm64",
+        };
+
+        write!(f, "{}/{}", os, arch)
+    }
+}
+
+/// A value that is either shared by all operating systems or given per operating system.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum PerOs<T> {
+    All(T),
+    Each { windows: T, macos: T, linux: T },
+}
+
+impl<T> PerOs<T> {
+    pub fn get(&self, os: TargetOs) -> &T {
+        match self {
+            PerOs::All(value) => value,
+            PerOs::Each {
+                windows,
+                macos,
+                linux,
+            } => match os {
+                TargetOs::Windows => windows,
+                TargetOs::MacOs => macos,
+                TargetOs::Linux => linux,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PerArch<T> {
+    pub amd64: T,
+    pub arm64: T,
+}
+
+impl<T> PerArch<T> {
+    pub fn get(&self, arch: TargetArch) -> &T {
+        match arch {
+            TargetArch::Amd64 => &self.amd64,
+            TargetArch::Arm64 => &self.arm64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Archive {
+    #[serde(rename = "none")]
+    None,
+    #[serde(rename = "tar.gz")]
+    TarGz,
+    #[serde(rename = "zip")]
+    Zip,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolFolder {
+    #[default]
+    Bin,
+    DockerCliPlugins,
+}
+
+/// How to ask an installed binary for its version, e.g. `kubectl version` and a regex whose
+/// first group captures the version.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct VersionProbe {
+    pub args: Vec<String>,
+    pub regex: String,
+}
+
+impl VersionProbe {
+    /// Runs `binary` with the probe's args and returns the version it reports, or `None` if its
+    /// output doesn't match the regex. Output of both streams is searched and the exit code is
+    /// ignored, since e.g. `docker version` fails without a daemon but still prints the client.
+    pub async fn run(&self, binary: &Path) -> Result<Option<String>> {
+        let regex = Regex::new(&format!("(?m){}", self.regex))
+            .map_err(|err| anyhow!("invalid version regex {}: {}", self.regex, err))?;
+        let output = Command::new(binary)
+            .args(&self.args)
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output();
+        let output = timeout(PROBE_TIMEOUT, output)
+            .await
+            .map_err(|_| anyhow!("{} didn't report its version in time", binary.display()))?
+            .map_err(|err| anyhow!("can't run {}: {}", binary.display(), err))?;
+
+        let text = format!(
+            "{}\n{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+
+        Ok(regex
+            .captures(&text)
+            .and_then(|captures| captures.get(1))
+            .map(|version| version.as_str().to_string()))
+    }
+}
+
+/// Describes where a tool is downloaded from, how it is unpacked and how its version is detected.
+///
+/// The url is a template, `{version}`, `{os}`, `{arch}` and `{ext}` are replaced with the
+/// expected version and the values configured for the target platform. The checksum url is a
+/// template as well and can additionally refer to the download url as `{url}`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Tool {
+    #[serde(skip)]
+    pub name: String,
+    pub url: String,
+    /// Url of the published SHA-256 checksum, either a single digest or a checksums.txt.
+    pub checksum: Option<String>,
+    #[serde(default = "default_os_names")]
+    pub os: PerOs<String>,
+    #[serde(default = "default_arch_names")]
+    pub arch: PerArch<String>,
+    #[serde(default = "default_ext")]
+    pub ext: PerOs<String>,
+    #[serde(default = "default_archive")]
+    pub archive: PerOs<Archive>,
+    /// Name of the installed file, defaults to the tool name.
+    pub file: Option<String>,
+    /// Path of the binary inside the archive, defaults to the installed file name.
+    pub binary: Option<String>,
+    /// Used by `krunch status` and `krunch doctor` to check what the installed binary reports.
+    pub version: Option<VersionProbe>,
+    #[serde(default)]
+    pub folder: ToolFolder,
+    /// Platforms for which the project publishes no release.
+    #[serde(default)]
+    pub unsupported: Vec<Platform>,
+    /// Where new versions are looked up, only needed for version ranges and `latest`.
+    pub releases: Option<ReleaseSource>,
+}
+
+impl Tool {
+    pub fn file_name(&self, platform: &Platform) -> String {
+        format!("{}{}", self.file_stem(), platform.exe_suffix())
+    }
+
+    /// Name of the installed file without `.exe`, which is how the tool is called.
+    pub fn file_stem(&self) -> &str {
+        self.file.as_deref().unwrap_or(&self.name)
+    }
+
+    pub fn binary_in_archive(&self, platform: &Platform) -> String {
+        match &self.binary {
+            Some(binary) => format!("{}{}", binary, platform.exe_suffix()),
+            None => self.file_name(platform),
+        }
+    }
+
+    pub fn archive(&self, platform: &Platform) -> Archive {
+        *self.archive.get(platform.os)
+    }
+
+    pub fn url(&self, platform: &Platform, version: &str) -> Result<Url> {
+        if self.unsupported.contains(platform) {
+            return Err(anyhow!("{} is not available for {}", self.name, platform));
+        }
+
+        self.render(&self.url, platform, version)
+    }
+
+    pub fn checksum_url(&self, platform: &Platform, version: &str) -> Result<Option<Url>> {
+        match &self.checksum {
+            Some(checksum) => {
+                let template = checksum.replace("{url}", &self.url);
+                Ok(Some(self.render(&template, platform, version)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn render(&self, template: &str, platform: &Platform, version: &str) -> Result<Url> {
+        let url = template
+            .replace("{version}", version)
+            .replace("{os}", self.os.get(platform.os))
+            .replace("{arch}", self.arch.get(platform.arch))
+            .replace("{ext}", self.ext.get(platform.os));
+
+        Url::parse(&url).map_err(|err| anyhow!("invalid url '{}' for {}: {}", url, self.name, err))
+    }
+
+    pub fn folder(&self) -> Result<PathBuf> {
+        match self.folder {
+            ToolFolder::Bin => get_bin_folder(),
+            ToolFolder::DockerCliPlugins => get_buildx_folder(),
+        }
+    }
+
+    /// Path of the shim that runs the tool.
+    pub fn install_path(&self) -> Result<PathBuf> {
+        Ok(self.folder()?.join(self.file_name(&Platform::current()?)))
+    }
+
+    /// Path of one installed version of the tool, shims pick one of them at exec time.
+    pub fn version_path(&self, version: &str, platform: &Platform) -> Result<PathBuf> {
+        Ok(get_tools_folder()?
+            .join(&self.name)
+            .join(version)
+            .join(self.file_name(platform)))
+    }
+
+    /// Returns the installed versions of the tool, the highest first.
+    pub fn installed_versions(&self) -> Result<Vec<String>> {
+        let folder = get_tools_folder()?.join(&self.name);
+        if !folder.exists() {
+            return Ok(vec![]);
+        }
+
+        let mut versions = vec![];
+        for entry in fs::read_dir(folder)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                versions.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+        versions.sort_by(|a, b| release_order(b).cmp(&release_order(a)));
+
+        Ok(versions)
+    }
+}
+
+/// Returns the built-in tools merged with the tools defined in the config file.
+pub fn get_tools() -> Result<BTreeMap<String, Tool>> {
+    let mut tools = get_builtin_tools()?;
+    tools.extend(get_config()?.tools);
+
+    for (name, tool) in tools.iter_mut() {
+        tool.name = name.clone();
+    }
+
+    Ok(tools)
+}
+
+/// The tools installed from a bundle, which may only be defined in the config of the machine
+/// that created it.
+pub fn get_bundled_tools() -> Result<BTreeMap<String, Tool>> {
+    read_definitions(&get_tools_folder()?)
+}
+
+/// Saves the definition of a tool installed from a bundle, so that its shim finds it.
+pub fn save_definition(tool: &Tool) -> Result<()> {
+    write_definition(&get_tools_folder()?, tool)
+}
+
+pub fn write_definition(tools_folder: &Path, tool: &Tool) -> Result<()> {
+    let folder = tools_folder.join(&tool.name);
+    fs::create_dir_all(&folder)?;
+    fs::write(
+        folder.join(DEFINITION_FILE),
+        serde_json::to_string_pretty(tool)?,
+    )?;
+
+    Ok(())
+}
+
+pub fn read_definitions(tools_folder: &Path) -> Result<BTreeMap<String, Tool>> {
+    let mut tools = BTreeMap::new();
+    if !tools_folder.exists() {
+        return Ok(tools);
+    }
+
+    for entry in fs::read_dir(tools_folder)? {
+        let path = entry?.path().join(DEFINITION_FILE);
+        if !path.is_file() {
+            continue;
+        }
+        let name = path
+            .parent()
+            .and_then(Path::file_name)
+            .ok_or(anyhow!("invalid tool definition path {}", path.display()))?
+            .to_string_lossy()
+            .to_string();
+        let mut tool: Tool = serde_json::from_str(&fs::read_to_string(&path)?)
+            .map_err(|err| anyhow!("invalid tool definition {}: {}", path.display(), err))?;
+        tool.name = name.clone();
+        tools.insert(name, tool);
+    }
+
+    Ok(tools)
+}
+
+/// The tools krunch knows without any config.
+pub fn get_builtin_tools() -> Result<BTreeMap<String, Tool>> {
+    let mut tools: BTreeMap<String, Tool> = serde_json::from_str(BUILTIN_TOOLS)?;
+
+    for (name, tool) in tools.iter_mut() {
+        tool.name = name.clone();
+    }
+
+    Ok(tools)
+}
+
+fn default_os_names() -> PerOs<String> {
+    PerOs::Each {
+        windows: "windows".to_string(),
+        macos: "darwin".to_string(),
+        linux: "linux".to_string(),
+    }
+}
+
+fn default_arch_names() -> PerArch<String> {
+    PerArch {
+        amd64: "amd64".to_string(),
+        arm64: "arm64".to_string(),
+    }
+}
+
+fn default_ext() -> PerOs<String> {
+    PerOs::Each {
+        windows: ".exe".to_string(),
+        macos: "".to_string(),
+        linux: "".to_string(),
+    }
+}
+
+fn default_archive() -> PerOs<Archive> {
+    PerOs::All(Archive::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_urls() {
+        let tools = get_builtin_tools().unwrap();
+        let linux = Platform {
+            os: TargetOs::Linux,
+            arch: TargetArch::Amd64,
+        };
+        let windows = Platform {
+            os: TargetOs::Windows,
+            arch: TargetArch::Amd64,
+        };
+        let mac = Platform {
+            os: TargetOs::MacOs,
+            arch: TargetArch::Arm64,
+        };
+
+        assert_eq!(
+            tools["docker"].url(&windows, "23.0.4").unwrap().as_str(),
+            "https://download.docker.com/win/static/stable/x86_64/docker-23.0.4.zip"
+        );
+        assert_eq!(
+            tools["docker"].url(&mac, "23.0.4").unwrap().as_str(),
+            "https://download.docker.com/mac/static/stable/aarch64/docker-23.0.4.tgz"
+        );
+        assert_eq!(
+            tools["helm"].url(&windows, "3.14.4").unwrap().as_str(),
+            "https://get.helm.sh/helm-v3.14.4-windows-amd64.zip"
+        );
+        assert_eq!(
+            tools["kubectl"].url(&linux, "1.28.3").unwrap().as_str(),
+            "https://dl.k8s.io/v1.28.3/bin/linux/amd64/kubectl"
+        );
+        assert_eq!(
+            tools["buildx"].url(&windows, "0.10.4").unwrap().as_str(),
+            "https://github.com/docker/buildx/releases/download/v0.10.4/buildx-v0.10.4.windows-amd64.exe"
+        );
+        assert_eq!(
+            tools["k9s"].url(&mac, "0.27.3").unwrap().as_str(),
+            "https://github.com/derailed/k9s/releases/download/v0.27.3/k9s_Darwin_arm64.tar.gz"
+        );
+        assert_eq!(
+            tools["mkcert"].url(&mac, "1.4.4").unwrap().as_str(),
+            "https://dl.filippo.io/mkcert/v1.4.4?for=darwin/arm64"
+        );
+        assert_eq!(
+            tools["helm"]
+                .checksum_url(&linux, "3.14.4")
+                .unwrap()
+                .unwrap()
+                .as_str(),
+            "https://get.helm.sh/helm-v3.14.4-linux-amd64.tar.gz.sha256sum"
+        );
+        assert_eq!(
+            tools["k9s"]
+                .checksum_url(&mac, "0.27.3")
+                .unwrap()
+                .unwrap()
+                .as_str(),
+            "https://github.com/derailed/k9s/releases/download/v0.27.3/checksums.sha256"
+        );
+        assert!(tools["docker"]
+            .url(&"windows/arm64".parse().unwrap(), "23.0.4")
+            .is_err());
+        assert_eq!(
+            tools["kubectl"]
+                .url(&"linux/aarch64".parse().unwrap(), "1.28.3")
+                .unwrap()
+                .as_str(),
+            "https://dl.k8s.io/v1.28.3/bin/linux/arm64/kubectl"
+        );
+        assert_eq!(tools["buildx"].file_name(&windows), "docker-buildx.exe");
+        assert_eq!(tools["docker"].binary_in_archive(&linux), "docker/docker");
+        assert_eq!(tools["helm"].archive(&windows), Archive::Zip);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_version_probe() {
+        let probe = |script: &str, regex: &str| VersionProbe {
+            args: vec!["-c".to_string(), script.to_string()],
+            regex: regex.to_string(),
+        };
+        let sh = Path::new("/bin/sh");
+
+        // kubectl prints its client version first and fails without a cluster
+        let kubectl = probe(
+            "echo 'Client Version: v1.28.3'; echo 'no cluster' >&2; exit 1",
+            "^Client Version.*?v([\\d\\.]+)",
+        );
+        assert_eq!(kubectl.run(sh).await.unwrap(), Some("1.28.3".to_string()));
+        assert_eq!(probe("echo other", "v(\\S+)").run(sh).await.unwrap(), None);
+        assert!(kubectl.run(Path::new("/nonexistent")).await.is_err());
+    }
+}
@@ -13,16 +13,14 @@ pub fn write_to_environment(key: &str, value: String) -> Result<()> {
         .arg("/d")
         .arg(value)
         .arg("/f")
-        .output()
-        .expect("failed to execute process");
+        .output()?;
 
     handle_output(output)?;
 
     let output = Command::new("SETX")
         .arg("USERNAME")
         .arg("%USERNAME%")
-        .output()
-        .expect("failed to execute process");
+        .output()?;
 
     handle_output(output)?;
 
@@ -35,8 +33,7 @@ pub fn read_from_environment(key: &str) -> Result<String> {
         .arg("HKEY_CURRENT_USER\\Environment")
         .arg("/v")
         .arg(key)
-        .output()
-        .expect("failed to execute process");
+        .output()?;
 
     let tmp = handle_output(output)?;
 
@@ -60,8 +57,7 @@ pub fn delete_from_environment(key: &str) -> Result<()> {
         .arg("/v")
         .arg(key)
         .arg("/f")
-        .output()
-        .expect("failed to execute process");
+        .output()?;
 
     handle_output(output)?;
 